@@ -0,0 +1,243 @@
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+
+/// Remote to connect to, parsed from `ssh://[user@]host[:port]`,
+/// `[user@]host[:port]` or `[user@][ipv6]:port`.
+///
+/// A bare IPv6 address without brackets cannot have a port.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Destination {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+fn parse_port(port: &str) -> Result<u16, String> {
+    match port.parse() {
+        Ok(0) | Err(_) => Err(format!("Invalid port {port:?}, expected 1-65535")),
+        Ok(port) => Ok(port),
+    }
+}
+
+impl FromStr for Destination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (uri, rest) = match s.strip_prefix("ssh://") {
+            Some(rest) => (true, rest.strip_suffix('/').unwrap_or(rest)),
+            None => (false, s),
+        };
+
+        if uri && rest.contains(['/', '?', '#']) {
+            return Err(format!(
+                "Unexpected path in {s:?}, expected ssh://[user@]host[:port]"
+            ));
+        }
+
+        let (user, rest) = match rest.rsplit_once('@') {
+            Some(("", _)) => return Err(format!("Empty user in {s:?}")),
+            Some((user, rest)) => (Some(user.to_string()), rest),
+            None => (None, rest),
+        };
+
+        let (host, port) = if let Some(rest) = rest.strip_prefix('[') {
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| format!("Missing ']' in {s:?}"))?;
+            host.parse::<Ipv6Addr>()
+                .map_err(|_| format!("Invalid IPv6 address {host:?} in {s:?}"))?;
+
+            let port = match rest {
+                "" => None,
+                rest => match rest.strip_prefix(':') {
+                    Some(port) => Some(parse_port(port)?),
+                    None => return Err(format!("Unexpected {rest:?} after ']' in {s:?}")),
+                },
+            };
+            (host, port)
+        } else if rest.matches(':').count() > 1 {
+            // Bare IPv6 address
+            rest.parse::<Ipv6Addr>().map_err(|_| {
+                format!("Invalid IPv6 address {rest:?}, use [address]:port to specify port")
+            })?;
+            (rest, None)
+        } else {
+            match rest.split_once(':') {
+                Some((host, port)) => (host, Some(parse_port(port)?)),
+                None => (rest, None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(format!("Empty host in {s:?}"));
+        }
+
+        let dest = Self {
+            user,
+            host: host.to_string(),
+            port,
+        };
+        dest.validate().map_err(|err| format!("{err} in {s:?}"))?;
+
+        Ok(dest)
+    }
+}
+
+impl Destination {
+    /// Check that host and user cannot be taken as options by ssh, which
+    /// is given the destination without "--".
+    pub fn validate(&self) -> Result<(), String> {
+        let host = &self.host;
+        if host.is_empty() || host.contains(|c: char| c.is_whitespace() || c == '/') {
+            return Err(format!("Invalid host {host:?}"));
+        }
+        if host.starts_with('-') {
+            return Err(format!("Host {host:?} must not start with '-'"));
+        }
+
+        if let Some(user) = &self.user {
+            if user.is_empty() || user.contains(char::is_whitespace) {
+                return Err(format!("Invalid user {user:?}"));
+            }
+            if user.starts_with('-') {
+                return Err(format!("User {user:?} must not start with '-'"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Print in the normalized form `[user@]host[:port]`, with IPv6 addresses
+/// in brackets.
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(user) = &self.user {
+            write!(f, "{user}@")?;
+        }
+
+        if self.host.contains(':') {
+            write!(f, "[{}]", self.host)?;
+        } else {
+            f.write_str(&self.host)?;
+        }
+
+        if let Some(port) = self.port {
+            write!(f, ":{port}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Destination, or hosts in ssh_config selected by `@group` or
+/// comma-separated patterns like `web-*,!web-3`.
+#[derive(Debug, Clone)]
+pub enum Target {
+    Destination(Destination),
+    Selector(String),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('@') || s.contains(['*', '?', '!', ',']) {
+            Ok(Target::Selector(s.to_string()))
+        } else {
+            s.parse().map(Target::Destination)
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Destination(dest) => dest.fmt(f),
+            Target::Selector(selector) => f.write_str(selector),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dest(user: Option<&str>, host: &str, port: Option<u16>) -> Destination {
+        Destination {
+            user: user.map(str::to_string),
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        for (s, expected) in [
+            ("example.com", dest(None, "example.com", None)),
+            (
+                "alice@example.com",
+                dest(Some("alice"), "example.com", None),
+            ),
+            ("example.com:2222", dest(None, "example.com", Some(2222))),
+            (
+                "a@b@example.com:22",
+                dest(Some("a@b"), "example.com", Some(22)),
+            ),
+            ("ssh://example.com", dest(None, "example.com", None)),
+            (
+                "ssh://alice@example.com:2222/",
+                dest(Some("alice"), "example.com", Some(2222)),
+            ),
+            ("[::1]:2222", dest(None, "::1", Some(2222))),
+            ("alice@[fe80::1]", dest(Some("alice"), "fe80::1", None)),
+            ("ssh://[::1]:22", dest(None, "::1", Some(22))),
+            ("::1", dest(None, "::1", None)),
+        ] {
+            assert_eq!(s.parse::<Destination>().unwrap(), expected, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        for s in [
+            "example.com",
+            "alice@example.com:2222",
+            "[::1]:22",
+            "alice@[fe80::1]",
+        ] {
+            assert_eq!(s.parse::<Destination>().unwrap().to_string(), s);
+        }
+        assert_eq!("::1".parse::<Destination>().unwrap().to_string(), "[::1]");
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!("example.com:0".parse::<Destination>().is_err());
+        assert!("[::1]22".parse::<Destination>().is_err());
+        assert!("ssh://example.com/path".parse::<Destination>().is_err());
+
+        // ssh would take these as an option.
+        for s in [
+            "-oProxyCommand=sh",
+            "alice@-oProxyCommand=sh",
+            "-alice@example.com",
+            "ssh://-oProxyCommand=sh",
+        ] {
+            assert!(s.parse::<Destination>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn test_target() {
+        for s in ["@web", "web-*", "db?", "web-*,!web-3", "a,b"] {
+            assert!(matches!(s.parse(), Ok(Target::Selector(selector)) if selector == s));
+        }
+        assert!(matches!(
+            "alice@web-1:22".parse(),
+            Ok(Target::Destination(_))
+        ));
+        assert!("-oProxyCommand=sh".parse::<Target>().is_err());
+    }
+}
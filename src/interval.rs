@@ -1,5 +1,4 @@
 use std::fmt;
-use std::num::ParseFloatError;
 use std::str::FromStr;
 use std::time::Duration;
 
@@ -7,13 +6,25 @@ use std::time::Duration;
 pub struct Interval(pub Duration);
 
 impl FromStr for Interval {
-    type Err = ParseFloatError;
+    type Err = String;
 
+    /// Parse seconds (can be float) with optional suffix "s", "ms", "µs"/"us" or "ns",
+    /// which also accepts the output of `Display`.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let s = s.strip_suffix('s').unwrap_or(s);
-        FromStr::from_str(s)
-            .map(Duration::from_secs_f64)
+        let (s, scale) = if let Some(s) = s.strip_suffix("ns") {
+            (s, 1e-9)
+        } else if let Some(s) = s.strip_suffix("µs").or_else(|| s.strip_suffix("us")) {
+            (s, 1e-6)
+        } else if let Some(s) = s.strip_suffix("ms") {
+            (s, 1e-3)
+        } else {
+            (s.strip_suffix('s').unwrap_or(s), 1.0)
+        };
+
+        let secs = f64::from_str(s).map_err(|err| format!("{err} in {s:?}"))?;
+        Duration::try_from_secs_f64(secs * scale)
             .map(Interval)
+            .map_err(|_| format!("Expected a non-negative finite interval, found {s}"))
     }
 }
 
@@ -28,3 +39,39 @@ impl fmt::Display for Interval {
         write!(f, "{:#?}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Duration, String> {
+        s.parse::<Interval>().map(|interval| interval.0)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1").unwrap(), Duration::from_secs(1));
+        assert_eq!(parse("0.5").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse("2s").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse("10ms").unwrap(), Duration::from_millis(10));
+        assert_eq!(parse("3us").unwrap(), Duration::from_micros(3));
+        assert_eq!(parse("3µs").unwrap(), Duration::from_micros(3));
+        assert_eq!(parse("7ns").unwrap(), Duration::from_nanos(7));
+    }
+
+    #[test]
+    fn test_parse_display() {
+        for secs in [0.0, 0.25, 1.5, 10.0] {
+            let interval = Interval(Duration::from_secs_f64(secs));
+            assert_eq!(parse(&interval.to_string()).unwrap(), interval.0);
+        }
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        // Duration::from_secs_f64 would panic on these.
+        assert!(parse("-0.5s").is_err());
+        assert!(parse("NaN").is_err());
+        assert!(parse("1e300").is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use super::options::Options;
+use super::ssh_config::matches_list;
+use super::Destination;
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Settings of a host, which can also be set on its groups.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct HostSettings {
+    /// Address to connect to, defaults to the name of the host.
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    /// Jump hosts to connect through in order.
+    pub jump: Vec<String>,
+    /// Options of ping, e.g. `interval = 0.5`.
+    pub ping: Options,
+    /// Options of speed-test, e.g. `transport = "sftp"`.
+    pub speed_test: Options,
+}
+
+impl HostSettings {
+    /// Fill the settings not set with those in `group`.
+    fn inherit(&mut self, group: &HostSettings) {
+        self.hostname = self.hostname.take().or_else(|| group.hostname.clone());
+        self.user = self.user.take().or_else(|| group.user.clone());
+        self.port = self.port.or(group.port);
+        if self.jump.is_empty() {
+            self.jump = group.jump.clone();
+        }
+        for (options, group_options) in [
+            (&mut self.ping, &group.ping),
+            (&mut self.speed_test, &group.speed_test),
+        ] {
+            for (key, value) in group_options {
+                options.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+struct HostConfig {
+    #[serde(flatten)]
+    settings: HostSettings,
+    groups: Vec<String>,
+    tags: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+struct GroupConfig {
+    #[serde(flatten)]
+    settings: HostSettings,
+    /// Members in addition to hosts listing this group.
+    hosts: Vec<String>,
+}
+
+/// Hosts and groups defined in a TOML or YAML file, e.g.
+///
+/// ```toml
+/// [groups.web]
+/// user = "deploy"
+/// jump = ["bastion.example.com"]
+///
+/// [hosts.web-1]
+/// hostname = "10.0.0.1"
+/// groups = ["web"]
+/// tags = ["prod"]
+/// ping = { interval = 0.5 }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct InventoryFile {
+    hosts: BTreeMap<String, HostConfig>,
+    groups: BTreeMap<String, GroupConfig>,
+}
+
+/// Host in inventory with the settings of its groups applied.
+#[derive(Debug, Clone)]
+pub struct Host {
+    pub name: String,
+    pub groups: Vec<String>,
+    pub tags: Vec<String>,
+    pub settings: HostSettings,
+}
+
+impl Host {
+    /// Address to connect to, which is hostname or the name.
+    pub fn address(&self) -> String {
+        self.settings
+            .hostname
+            .clone()
+            .unwrap_or_else(|| self.name.clone())
+    }
+
+    pub fn destination(&self) -> Destination {
+        Destination {
+            user: self.settings.user.clone(),
+            host: self.address(),
+            port: self.settings.port,
+        }
+    }
+
+    pub fn jump_hosts(&self) -> Result<Vec<Destination>, String> {
+        self.settings
+            .jump
+            .iter()
+            .map(|jump| {
+                jump.parse()
+                    .map_err(|err| format!("Invalid jump host of {}: {err}", self.name))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Inventory {
+    pub hosts: Vec<Host>,
+}
+
+impl Inventory {
+    /// Load the inventory, as YAML if the extension is yaml/yml,
+    /// otherwise as TOML.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+        let invalid = |err: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {err}", path.display()),
+            )
+        };
+
+        let file: InventoryFile = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => {
+                serde_yaml::from_str(&content).map_err(|err| invalid(err.to_string()))?
+            }
+            _ => toml::from_str(&content).map_err(|err| invalid(err.to_string()))?,
+        };
+
+        let mut hosts = Vec::with_capacity(file.hosts.len());
+        for (name, config) in &file.hosts {
+            let mut groups = config.groups.clone();
+            for (group, group_config) in &file.groups {
+                if group_config.hosts.contains(name) && !groups.contains(group) {
+                    groups.push(group.clone());
+                }
+            }
+
+            let mut settings = config.settings.clone();
+            for group in &groups {
+                match file.groups.get(group) {
+                    Some(group_config) => settings.inherit(&group_config.settings),
+                    None => return Err(invalid(format!("Undefined group {group} of {name}"))),
+                }
+            }
+
+            let host = Host {
+                name: name.clone(),
+                groups,
+                tags: config.tags.clone(),
+                settings,
+            };
+            host.destination()
+                .validate()
+                .map_err(|err| invalid(format!("Invalid host {name}: {err}")))?;
+            hosts.push(host);
+        }
+
+        for (group, group_config) in &file.groups {
+            if let Some(name) = group_config
+                .hosts
+                .iter()
+                .find(|name| !file.hosts.contains_key(*name))
+            {
+                return Err(invalid(format!("Undefined host {name} in group {group}")));
+            }
+        }
+
+        Ok(Self { hosts })
+    }
+
+    /// Return hosts selected by `selector` (`@group` or comma-separated
+    /// patterns of names) in any of `groups` (or all if empty) that have
+    /// all `tags`.
+    pub fn select(&self, selector: Option<&str>, groups: &[String], tags: &[String]) -> Vec<&Host> {
+        self.hosts
+            .iter()
+            .filter(|host| match selector {
+                None => true,
+                Some(selector) => match selector.strip_prefix('@') {
+                    Some(group) => host.groups.iter().any(|g| g == group),
+                    None => matches_list(selector, &host.name),
+                },
+            })
+            .filter(|host| groups.is_empty() || groups.iter().any(|g| host.groups.contains(g)))
+            .filter(|host| tags.iter().all(|tag| host.tags.contains(tag)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn load(ext: &str, content: &str) -> io::Result<Inventory> {
+        let mut file = NamedTempFile::with_suffix(ext).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        Inventory::load(file.path())
+    }
+
+    const INVENTORY: &str = r#"
+[groups.web]
+user = "deploy"
+port = 2222
+jump = ["bastion"]
+ping = { interval = 0.5, count = 3 }
+hosts = ["web-2"]
+
+[groups.db]
+user = "postgres"
+
+[hosts.web-1]
+hostname = "10.0.0.1"
+user = "alice"
+groups = ["web"]
+tags = ["prod"]
+ping = { count = 5 }
+
+[hosts.web-2]
+tags = ["prod", "eu"]
+
+[hosts.db-1]
+groups = ["db", "web"]
+"#;
+
+    #[test]
+    fn test_load() {
+        let inventory = load(".toml", INVENTORY).unwrap();
+        let [db_1, web_1, web_2] = &inventory.hosts[..] else {
+            panic!("Unexpected {:?}", inventory.hosts);
+        };
+
+        assert_eq!(web_1.destination().to_string(), "alice@10.0.0.1:2222");
+        assert_eq!(web_1.jump_hosts().unwrap()[0].to_string(), "bastion");
+        assert_eq!(web_1.settings.ping["count"].to_string(), "5");
+        assert_eq!(web_1.settings.ping["interval"].to_string(), "0.5");
+
+        assert_eq!(web_2.groups, ["web"]);
+        assert_eq!(web_2.address(), "web-2");
+        assert_eq!(web_2.destination().to_string(), "deploy@web-2:2222");
+
+        // Groups listed first take precedence.
+        assert_eq!(db_1.destination().to_string(), "postgres@db-1:2222");
+    }
+
+    #[test]
+    fn test_load_yaml() {
+        let inventory = load(
+            ".yaml",
+            "groups:\n  web:\n    user: deploy\nhosts:\n  web-1:\n    groups: [web]\n",
+        )
+        .unwrap();
+        assert_eq!(inventory.hosts[0].destination().to_string(), "deploy@web-1");
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let err = load(".toml", "[groups.web]\nhosts = [\"web-1\"]\n").unwrap_err();
+        assert!(err.to_string().contains("Undefined host web-1"), "{err}");
+
+        // ssh would take the hostname as an option.
+        let err = load(".toml", "[hosts.web-1]\nhostname = \"-oProxyCommand=sh\"\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_select() {
+        let inventory = load(".toml", INVENTORY).unwrap();
+        let select = |selector, groups: &[&str], tags: &[&str]| {
+            let groups: Vec<_> = groups.iter().map(|g| g.to_string()).collect();
+            let tags: Vec<_> = tags.iter().map(|t| t.to_string()).collect();
+            let hosts = inventory.select(selector, &groups, &tags);
+            hosts
+                .iter()
+                .map(|host| host.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(select(None, &[], &[]), ["db-1", "web-1", "web-2"]);
+        assert_eq!(select(Some("web-*,!web-2"), &[], &[]), ["web-1"]);
+        assert_eq!(select(Some("@web"), &[], &[]), ["db-1", "web-1", "web-2"]);
+        assert_eq!(select(None, &["db"], &[]), ["db-1"]);
+        assert_eq!(select(None, &["db", "web"], &["prod"]), ["web-1", "web-2"]);
+        assert_eq!(select(None, &[], &["prod", "eu"]), ["web-2"]);
+        assert!(select(Some("db-1"), &[], &["prod"]).is_empty());
+    }
+}
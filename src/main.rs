@@ -4,16 +4,30 @@ use ping::PingArgs;
 mod interval;
 use interval::Interval;
 
+mod destination;
+use destination::{Destination, Target};
+
+mod ssh_config;
+use ssh_config::{HostEntry, SshConfig};
+
+mod options;
+
+mod settings;
+use settings::Settings;
+
+mod inventory;
+use inventory::{Host, Inventory};
+
 mod utility;
 use utility::eprintln_error;
 
 mod ssh_session_builder;
-use ssh_session_builder::SshSessionBuilder;
+use ssh_session_builder::{ControlPath, KnownHostsPolicy, SshSessionBuilder};
 
 mod speedtest;
 use speedtest::SpeedTestArgs;
 
-use clap::{IntoApp, Parser};
+use clap::{FromArgMatches, IntoApp, Parser};
 use clap_verbosity_flag::Verbosity;
 use openssh::SessionBuilder;
 use std::path::PathBuf;
@@ -24,41 +38,359 @@ use std::process::exit;
 #[clap(author, version, about, long_about = None)]
 struct Args {
     /// Verbosity.
+    ///
+    /// Verbosity can also be set by environment variable
+    /// SSH_UTILS_VERBOSITY (off, error, warn, info, debug or trace).
     #[clap(flatten)]
     verbose: Verbosity,
 
-    #[clap(short, long, default_value_t = Interval::from_secs(10))]
+    #[clap(short, long, env = "SSH_UTILS_TIMEOUT", default_value_t = Interval::from_secs(10))]
     timeout: Interval,
 
+    /// Profile in ~/.config/ssh-utils/config.toml to take defaults from,
+    /// e.g. a "wan" profile with longer timeouts.
+    #[clap(long, env = "SSH_UTILS_PROFILE")]
+    profile: Option<String>,
+
     #[clap(short = 'F', long)]
     config_file: Option<PathBuf>,
 
+    /// User to login as on remote, overriding the user in inventory.
+    #[clap(short = 'l', long)]
+    user: Option<String>,
+
+    /// Port to connect to on remote, overriding the port in inventory.
+    #[clap(short, long)]
+    port: Option<u16>,
+
+    /// Comma-separated jump hosts to connect through in order,
+    /// each in the same form as the destination.
+    #[clap(short = 'J', long, use_value_delimiter = true)]
+    jump_hosts: Vec<Destination>,
+
+    /// Identity (private key) file to authenticate with.
+    #[clap(short, long)]
+    identity: Option<PathBuf>,
+
+    /// How to check the host key against known_hosts: strict, add or accept.
+    #[clap(long)]
+    known_hosts: Option<KnownHostsPolicy>,
+
+    /// Directory to create the control socket of ssh multiplex master in.
+    #[clap(long)]
+    control_dir: Option<PathBuf>,
+
+    /// Interval of sending keepalive to remote in seconds (can be float).
+    #[clap(long)]
+    server_alive_interval: Option<Interval>,
+
+    /// Resume the ssh multiplex master listening on this control socket
+    /// instead of creating a new one.
+    ///
+    /// Pass "auto" to use the ControlPath from ssh_config, which is
+    /// required when multiple hosts are selected.
+    #[clap(long)]
+    control_path: Option<ControlPath>,
+
+    /// TOML or YAML file of hosts to select from, with their groups, tags
+    /// and per-host user, port, jump hosts and subcommand options.
+    ///
+    /// Files with extension yaml or yml are read as YAML.
+    #[clap(long)]
+    inventory: Option<PathBuf>,
+
+    /// Select hosts in inventory in any of these comma-separated groups.
+    #[clap(long, requires = "inventory", use_value_delimiter = true)]
+    group: Vec<String>,
+
+    /// Select hosts in inventory having all of these comma-separated tags.
+    #[clap(long, requires = "inventory", use_value_delimiter = true)]
+    tag: Vec<String>,
+
     #[clap(subcommand)]
     subcommand: SubCommand,
 
-    /// hostname to ping.
+    /// Remote to connect to: [user@]host[:port], [user@][ipv6]:port
+    /// or ssh://[user@]host[:port].
+    ///
+    /// Hosts in ssh_config can also be selected by comma-separated
+    /// patterns like 'web-*,!web-3', or by '@group'. ssh_config has no
+    /// groups, so a group is the hosts declared in an included file named
+    /// after it, e.g. '@prod' selects the hosts of 'Include config.d/prod'.
+    ///
+    /// With --inventory, hosts in inventory are selected instead, and
+    /// hostname can be omitted to select by --group and --tag.
     #[clap(global(true))]
-    hostname: Option<String>,
+    hostname: Option<Target>,
 }
 
 #[derive(clap::Subcommand, Debug)]
 enum SubCommand {
     Ping(PingArgs),
-    SpeedTest(SpeedTestArgs),
+    SpeedTest(Box<SpeedTestArgs>),
+    /// List hosts declared in ssh_config (or inventory), filtered by
+    /// hostname if given.
+    ///
+    /// For ssh_config, the hostname, user and port shown are those declared
+    /// in the blocks naming the host, excluding wildcard blocks like
+    /// 'Host *', so they may differ from what ssh uses.
+    Hosts,
+}
+
+/// Destination to run the subcommand on, with its host in inventory.
+///
+/// The user and port of the host in inventory are not in `dest`, so that
+/// --user and --port take precedence over them.
+struct Job<'a> {
+    dest: Destination,
+    host: Option<&'a Host>,
+}
+
+/// Return the jobs of hosts in `inventory` selected by `target`, `groups`
+/// and `tags`.
+///
+/// A destination not in inventory is used as is if neither `groups` nor
+/// `tags` is given.
+fn inventory_jobs<'a>(
+    inventory: &'a Inventory,
+    target: Option<&Target>,
+    groups: &[String],
+    tags: &[String],
+) -> Result<Vec<Job<'a>>, String> {
+    let selector = match target {
+        Some(Target::Destination(dest)) => {
+            return match inventory.select(Some(&dest.host), groups, tags)[..] {
+                [host] => Ok(vec![Job {
+                    dest: Destination {
+                        user: dest.user.clone(),
+                        host: host.address(),
+                        port: dest.port,
+                    },
+                    host: Some(host),
+                }]),
+                _ if !groups.is_empty() || !tags.is_empty() => Err(format!(
+                    "{} is not in inventory with the selected groups and tags",
+                    dest.host
+                )),
+                _ => Ok(vec![Job {
+                    dest: dest.clone(),
+                    host: None,
+                }]),
+            };
+        }
+        Some(Target::Selector(selector)) => Some(&**selector),
+        None => None,
+    };
+
+    let jobs: Vec<_> = inventory
+        .select(selector, groups, tags)
+        .into_iter()
+        .map(|host| Job {
+            dest: Destination {
+                user: None,
+                host: host.address(),
+                port: None,
+            },
+            host: Some(host),
+        })
+        .collect();
+
+    if jobs.is_empty() {
+        Err("No host in inventory matches the selection".to_string())
+    } else {
+        Ok(jobs)
+    }
+}
+
+/// Return the destinations of `target`, expanding selectors with the
+/// hosts in ssh_config.
+fn destinations(target: &Target, config: Option<&SshConfig>) -> Result<Vec<Destination>, String> {
+    let selector = match target {
+        Target::Destination(dest) => return Ok(vec![dest.clone()]),
+        Target::Selector(selector) => selector,
+    };
+
+    let destinations = config
+        .map(|config| config.select(selector))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|host| {
+            let dest = Destination {
+                user: None,
+                host: host.name.clone(),
+                port: None,
+            };
+            dest.validate()
+                .map(|_| dest)
+                .map_err(|err| format!("Invalid host in ssh_config: {err}"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if destinations.is_empty() {
+        Err(format!("No host in ssh_config matches {selector}"))
+    } else {
+        Ok(destinations)
+    }
+}
+
+fn print_hosts(hosts: &[&HostEntry]) {
+    let width = hosts.iter().map(|host| host.name.len()).max().unwrap_or(0);
+
+    for host in hosts {
+        print!("{:<width$}", host.name);
+        for (key, value) in [
+            ("hostname", &host.hostname),
+            ("user", &host.user),
+            ("port", &host.port),
+        ] {
+            if let Some(value) = value {
+                print!("  {key}={value}");
+            }
+        }
+        if let Some(group) = &host.group {
+            print!("  @{group}");
+        }
+        println!();
+    }
+}
+
+fn print_inventory_hosts(hosts: &[&Host]) {
+    let width = hosts.iter().map(|host| host.name.len()).max().unwrap_or(0);
+
+    for host in hosts {
+        print!("{:<width$}  {}", host.name, host.destination());
+        if !host.settings.jump.is_empty() {
+            print!("  jump={}", host.settings.jump.join(","));
+        }
+        for group in &host.groups {
+            print!("  @{group}");
+        }
+        for tag in &host.tags {
+            print!("  #{tag}");
+        }
+        println!();
+    }
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+
+    let profile = Settings::load()
+        .map_err(|err| err.to_string())
+        .and_then(|settings| settings.profile(args.profile.as_deref()))
+        .unwrap_or_else(|err| {
+            eprintln_error!("Failed to read config file: {err}");
+            exit(1)
+        });
+
+    if !options::is_given(&matches, "timeout") {
+        if let Some(timeout) = profile.timeout {
+            args.timeout = timeout;
+        }
+    }
+    if matches.occurrences_of("verbose") == 0 && matches.occurrences_of("quiet") == 0 {
+        match std::env::var("SSH_UTILS_VERBOSITY") {
+            Ok(verbosity) => match settings::parse_verbosity(&verbosity) {
+                Ok(verbosity) => args.verbose = verbosity,
+                Err(err) => {
+                    eprintln_error!("Invalid SSH_UTILS_VERBOSITY: {err}");
+                    exit(1)
+                }
+            },
+            Err(_) => {
+                if let Some(verbosity) = profile.verbosity.clone() {
+                    args.verbose = verbosity;
+                }
+            }
+        }
+    }
+
+    let inventory = args.inventory.as_ref().map(|path| {
+        Inventory::load(path).unwrap_or_else(|err| {
+            eprintln_error!("Failed to read inventory: {err}");
+            exit(1)
+        })
+    });
+
+    let config = match (&args.subcommand, args.hostname.as_ref()) {
+        _ if inventory.is_some() => None,
+        (SubCommand::Hosts, _) | (_, Some(Target::Selector(_))) => {
+            match SshConfig::load(args.config_file.as_deref()) {
+                Ok(config) => Some(config),
+                Err(err) => {
+                    eprintln_error!("Failed to read ssh_config: {err}");
+                    exit(1)
+                }
+            }
+        }
+        _ => None,
+    };
+
+    if let (SubCommand::Hosts, Some(inventory)) = (&args.subcommand, inventory.as_ref()) {
+        let hosts = match args.hostname.as_ref() {
+            Some(Target::Selector(selector)) => {
+                inventory.select(Some(selector), &args.group, &args.tag)
+            }
+            Some(Target::Destination(dest)) => {
+                inventory.select(Some(&dest.host), &args.group, &args.tag)
+            }
+            None => inventory.select(None, &args.group, &args.tag),
+        };
+        print_inventory_hosts(&hosts);
+        return;
+    }
+
+    if let SubCommand::Hosts = args.subcommand {
+        let config = config.unwrap_or_default();
+        let hosts = match args.hostname.as_ref() {
+            Some(Target::Selector(selector)) => config.select(selector),
+            Some(Target::Destination(dest)) => config.select(&dest.host),
+            None => config.hosts.iter().collect(),
+        };
+        print_hosts(&hosts);
+        return;
+    }
 
-    let hostname = match args.hostname.as_ref() {
-        Some(hostname) => hostname,
-        None => {
+    let jobs = match (args.hostname.as_ref(), inventory.as_ref()) {
+        (target, Some(inventory)) => inventory_jobs(inventory, target, &args.group, &args.tag),
+        (Some(target), None) => destinations(target, config.as_ref()).map(|destinations| {
+            destinations
+                .into_iter()
+                .map(|dest| Job { dest, host: None })
+                .collect()
+        }),
+        (None, None) => {
             eprintln_error!("ERROR: Expected positional argument hostname!\n");
             Args::command().print_long_help().unwrap();
             exit(1)
         }
     };
+    let jobs = jobs.unwrap_or_else(|err| {
+        eprintln_error!("ERROR: {err}");
+        exit(1)
+    });
+
+    // A control socket belongs to a single host.
+    if jobs.len() > 1 && matches!(args.control_path, Some(ControlPath::Path(_))) {
+        eprintln_error!("ERROR: --control-path must be auto to run on multiple hosts");
+        exit(1)
+    }
+
+    // Hosts are pinged one after another, so each must stop. Without
+    // inventory, all of them have the same count.
+    if let (SubCommand::Ping(_), None) = (&args.subcommand, &inventory) {
+        let ping_args = options::with_defaults::<PingArgs>(
+            "ping",
+            matches.subcommand_matches("ping").unwrap(),
+            &profile.ping,
+        );
+        if jobs.len() > 1 && matches!(ping_args, Ok(ping_args) if ping_args.is_unlimited()) {
+            eprintln_error!("ERROR: --count is required to ping multiple hosts");
+            exit(1)
+        }
+    }
 
     let mut builder = SessionBuilder::default();
 
@@ -66,18 +398,117 @@ async fn main() {
     if let Some(config_file) = args.config_file.as_ref() {
         builder.config_file(config_file);
     }
+    if let Some(identity) = args.identity.as_ref() {
+        builder.keyfile(identity);
+    }
+    if let Some(known_hosts) = args.known_hosts.as_ref() {
+        builder.known_hosts_check(known_hosts.0.clone());
+    }
+    if let Some(control_dir) = args.control_dir.as_ref() {
+        builder.control_directory(control_dir);
+    }
+    if let Some(server_alive_interval) = args.server_alive_interval {
+        builder.server_alive_interval(server_alive_interval.0);
+    }
+
+    let mut failed = false;
+    for Job { dest, host } in &jobs {
+        if jobs.len() > 1 {
+            println!("=== {dest} ===");
+        }
 
-    let builder = SshSessionBuilder::new(builder, hostname);
+        let jump_hosts = match host {
+            Some(host) if args.jump_hosts.is_empty() => match host.jump_hosts() {
+                Ok(jump_hosts) => jump_hosts,
+                Err(err) => {
+                    eprintln_error!("ERROR: {err}");
+                    failed = true;
+                    continue;
+                }
+            },
+            _ => args.jump_hosts.clone(),
+        };
 
-    let res = match args.subcommand {
-        SubCommand::Ping(ping_args) => ping::run(ping_args, args.verbose, builder).await,
-        SubCommand::SpeedTest(speedtest_args) => {
-            speedtest::run(speedtest_args, args.verbose, builder).await
+        let mut builder = SshSessionBuilder::new(builder.clone(), dest);
+        builder
+            .config_file(args.config_file.as_deref())
+            .control_path(args.control_path.as_ref())
+            .user(
+                args.user
+                    .as_deref()
+                    .or_else(|| (*host)?.settings.user.as_deref()),
+            )
+            .port(args.port.or_else(|| (*host)?.settings.port))
+            .jump_hosts(&jump_hosts);
+
+        let res = match &args.subcommand {
+            SubCommand::Ping(_) => {
+                let mut ping_options = profile.ping.clone();
+                if let Some(host) = host {
+                    ping_options.extend(host.settings.ping.clone());
+                }
+                let ping_args = options::with_defaults::<PingArgs>(
+                    "ping",
+                    matches.subcommand_matches("ping").unwrap(),
+                    &ping_options,
+                )
+                .map_err(|err| err.to_string())
+                .and_then(|ping_args| {
+                    // Hosts in inventory can set their own count.
+                    if jobs.len() > 1 && host.is_some() && ping_args.is_unlimited() {
+                        Err("--count is required to ping multiple hosts".to_string())
+                    } else {
+                        Ok(ping_args)
+                    }
+                });
+                match ping_args {
+                    Ok(ping_args) => ping::run(ping_args, args.verbose.clone(), builder).await,
+                    Err(err) => {
+                        eprintln_error!("Invalid ping options of {dest}: {err}");
+                        failed = true;
+                        continue;
+                    }
+                }
+            }
+            SubCommand::SpeedTest(_) => {
+                let mut speedtest_options = profile.speed_test.clone();
+                if let Some(host) = host {
+                    speedtest_options.extend(host.settings.speed_test.clone());
+                }
+                let speedtest_args = options::with_defaults::<SpeedTestArgs>(
+                    "speed-test",
+                    matches.subcommand_matches("speed-test").unwrap(),
+                    &speedtest_options,
+                )
+                .map_err(|err| err.to_string())
+                .and_then(|speedtest_args| {
+                    speedtest_args.check_requires()?;
+                    if args.control_path.is_some() {
+                        speedtest_args.check_resumable()?;
+                    }
+                    Ok(speedtest_args)
+                });
+                match speedtest_args {
+                    Ok(speedtest_args) => {
+                        speedtest::run(speedtest_args, args.verbose.clone(), builder).await
+                    }
+                    Err(err) => {
+                        eprintln_error!("Invalid speed-test options of {dest}: {err}");
+                        failed = true;
+                        continue;
+                    }
+                }
+            }
+            SubCommand::Hosts => unreachable!(),
+        };
+
+        if let Err(error) = res {
+            eprintln_error!("Failed to login to {}: {:#?}!", dest, error);
+            failed = true;
         }
-    };
+    }
 
-    if let Err(error) = res {
-        eprintln_error!("Failed to login to {}: {:#?}!", hostname, error);
+    if failed {
         exit(1);
     }
 }
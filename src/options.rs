@@ -0,0 +1,197 @@
+use clap::{ArgMatches, Parser, ValueSource};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt;
+
+/// Value of an option in a file, e.g. `interval = 0.5`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum OptionValue {
+    Bool(bool),
+    Integer(i64),
+    Float(f64),
+    String(String),
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionValue::Bool(value) => value.fmt(f),
+            OptionValue::Integer(value) => value.fmt(f),
+            OptionValue::Float(value) => value.fmt(f),
+            OptionValue::String(value) => value.fmt(f),
+        }
+    }
+}
+
+/// Options keyed by their long name, e.g. `report-interval`.
+pub type Options = BTreeMap<String, OptionValue>;
+
+/// Return true if the arg is given on the command line or via
+/// environment variable.
+pub fn is_given(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+/// Parse `T` (the args of subcommand `name`) again, using `options` in
+/// place of the defaults of args that are not given.
+///
+/// clap checks `requires` of the command line alone, before `options`
+/// are applied, so an arg that can be satisfied by `options` must be
+/// checked after this instead.
+pub fn with_defaults<T: Parser>(
+    name: &str,
+    matches: &ArgMatches,
+    options: &Options,
+) -> Result<T, clap::Error> {
+    let command = T::command();
+    let mut argv: Vec<OsString> = vec![name.into()];
+
+    for key in options.keys() {
+        if !command
+            .get_arguments()
+            .any(|arg| arg.get_long() == Some(key))
+        {
+            return Err(clap::Error::raw(
+                clap::ErrorKind::UnknownArgument,
+                format!("Unknown option {key} for {name}"),
+            ));
+        }
+    }
+
+    for arg in command.get_arguments() {
+        let (id, long) = match arg.get_long() {
+            // Generated by clap, not in `matches`.
+            Some("help" | "version") => continue,
+            Some(long) => (arg.get_id(), long),
+            None => continue,
+        };
+
+        if is_given(matches, id) {
+            if arg.is_takes_value_set() {
+                for value in matches.get_raw(id).into_iter().flatten() {
+                    argv.push(format!("--{long}").into());
+                    argv.push(value.into());
+                }
+            } else {
+                for _ in 0..matches.occurrences_of(id) {
+                    argv.push(format!("--{long}").into());
+                }
+            }
+        } else if let Some(value) = options.get(long) {
+            match value {
+                OptionValue::Bool(false) => (),
+                OptionValue::Bool(true) => argv.push(format!("--{long}").into()),
+                value => {
+                    argv.push(format!("--{long}").into());
+                    argv.push(value.to_string().into());
+                }
+            }
+        }
+    }
+
+    T::try_parse_from(argv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::{ErrorKind, IntoApp};
+
+    #[derive(Parser, Debug)]
+    struct TestArgs {
+        #[clap(short, long, default_value_t = 1)]
+        count: u64,
+
+        #[clap(long)]
+        bytes: Option<String>,
+
+        #[clap(long)]
+        verify: bool,
+
+        #[clap(long, use_value_delimiter = true)]
+        ciphers: Vec<String>,
+
+        #[clap(long, env = "SSH_UTILS_TEST_INTERVAL", default_value_t = 1)]
+        interval: u64,
+    }
+
+    fn parse(argv: &[&str], options: &[(&str, OptionValue)]) -> Result<TestArgs, clap::Error> {
+        let matches = TestArgs::command()
+            .try_get_matches_from(["test"].iter().chain(argv))
+            .unwrap();
+        let options = options
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+        with_defaults("test", &matches, &options)
+    }
+
+    #[test]
+    fn test_with_defaults() {
+        let args = parse(&[], &[]).unwrap();
+        assert_eq!(args.count, 1);
+        assert_eq!(args.bytes, None);
+        assert!(!args.verify);
+
+        let args = parse(
+            &[],
+            &[
+                ("count", OptionValue::Integer(5)),
+                ("bytes", OptionValue::String("1M".to_string())),
+                ("verify", OptionValue::Bool(true)),
+                ("ciphers", OptionValue::String("a,b".to_string())),
+            ],
+        )
+        .unwrap();
+        assert_eq!(args.count, 5);
+        assert_eq!(args.bytes.as_deref(), Some("1M"));
+        assert!(args.verify);
+        assert_eq!(args.ciphers, ["a", "b"]);
+
+        let args = parse(&["--verify"], &[("verify", OptionValue::Bool(false))]).unwrap();
+        assert!(args.verify);
+    }
+
+    #[test]
+    fn test_with_defaults_command_line_first() {
+        let args = parse(
+            &["-c", "3", "--ciphers", "c", "--ciphers", "d"],
+            &[
+                ("count", OptionValue::Integer(5)),
+                ("ciphers", OptionValue::String("a,b".to_string())),
+            ],
+        )
+        .unwrap();
+        assert_eq!(args.count, 3);
+        assert_eq!(args.ciphers, ["c", "d"]);
+    }
+
+    #[test]
+    fn test_with_defaults_env() {
+        let options = [("interval", OptionValue::Integer(2))];
+
+        std::env::remove_var("SSH_UTILS_TEST_INTERVAL");
+        assert_eq!(parse(&[], &[]).unwrap().interval, 1);
+        assert_eq!(parse(&[], &options).unwrap().interval, 2);
+
+        std::env::set_var("SSH_UTILS_TEST_INTERVAL", "3");
+        assert_eq!(parse(&[], &options).unwrap().interval, 3);
+        assert_eq!(parse(&["--interval", "4"], &options).unwrap().interval, 4);
+        std::env::remove_var("SSH_UTILS_TEST_INTERVAL");
+    }
+
+    #[test]
+    fn test_with_defaults_invalid() {
+        let err = parse(&[], &[("size", OptionValue::Integer(5))]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnknownArgument);
+
+        let err = parse(&[], &[("count", OptionValue::Float(0.5))]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ValueValidation);
+    }
+}
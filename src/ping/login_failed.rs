@@ -11,7 +11,7 @@ use tokio::time::{interval, MissedTickBehavior};
 pub async fn main_loop(
     args: PingArgs,
     verbose: Verbosity,
-    builder: SshSessionBuilder<'_>,
+    builder: &SshSessionBuilder<'_>,
     stats: &mut Vec<Duration>,
 ) -> Result<(), Error> {
     let mut interval = interval(args.interval.0);
@@ -45,7 +45,10 @@ pub async fn main_loop(
                 let mut args = args;
                 args.count -= seq + 1;
 
-                return logined::main_loop(args, verbose, session, stats).await;
+                let res = logined::main_loop(args, verbose, &session, stats).await;
+                builder.close(session).await?;
+
+                return res;
             }
             Err(error) => match error {
                 Error::Connect(err) if err.kind() == io::ErrorKind::PermissionDenied => {
@@ -82,7 +82,7 @@ async fn main_loop_impl(
 pub async fn main_loop(
     args: PingArgs,
     verbose: Verbosity,
-    session: Session,
+    session: &Session,
     stats: &mut Vec<Duration>,
 ) -> Result<(), Error> {
     println_on_level!(verbose, Level::Debug, "Spawning process cat on remote");
@@ -114,7 +114,5 @@ pub async fn main_loop(
             child.disconnect().await.map_err(Error::Remote)?;
             Ok::<_, Error>(())
         },
-    }?;
-
-    session.close().await
+    }
 }
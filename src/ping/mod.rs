@@ -1,8 +1,8 @@
 mod login_failed;
-mod logined;
+pub mod logined;
 
 mod stats;
-use stats::Stats;
+pub use stats::Stats;
 
 use super::utility::{println_if_not_quiet, println_on_level, Level};
 use super::{Interval, SshSessionBuilder};
@@ -16,7 +16,7 @@ use std::num::NonZeroU64;
 #[derive(Debug, Parser, Copy, Clone)]
 pub struct PingArgs {
     /// Interval of pinging in seconds (can be float).
-    #[clap(short, long, default_value_t = Interval::from_secs(1))]
+    #[clap(short, long, env = "SSH_UTILS_PING_INTERVAL", default_value_t = Interval::from_secs(1))]
     interval: Interval,
 
     /// Number of packets to sent.
@@ -28,6 +28,21 @@ pub struct PingArgs {
     size: NonZeroU64,
 }
 
+impl PingArgs {
+    pub fn new(interval: Interval, count: u64, size: NonZeroU64) -> Self {
+        Self {
+            interval,
+            count,
+            size,
+        }
+    }
+
+    /// Return true if pinging until interrupted, as -c is not given.
+    pub fn is_unlimited(&self) -> bool {
+        self.count == u64::MAX
+    }
+}
+
 pub async fn run(
     args: PingArgs,
     verbose: Verbosity,
@@ -43,12 +58,14 @@ pub async fn run(
     let res = match res {
         Ok(session) => {
             println_on_level!(verbose, Level::Debug, "Successfully login into {dest}");
-            logined::main_loop(args, verbose, session, &mut stats).await
+            let res = logined::main_loop(args, verbose, &session, &mut stats).await;
+            let close_res = builder.close(session).await;
+            res.and(close_res)
         }
         Err(error) => match error {
             Error::Connect(err) if err.kind() == io::ErrorKind::PermissionDenied => {
                 println_on_level!(verbose, Level::Warn, "Cannot login to {dest}");
-                login_failed::main_loop(args, verbose, builder, &mut stats).await
+                login_failed::main_loop(args, verbose, &builder, &mut stats).await
             }
             error => Err(error),
         },
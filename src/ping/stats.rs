@@ -46,11 +46,7 @@ impl Stats {
         let sum: u128 = iter
             .clone()
             .map(|micros| {
-                let diff = if micros >= avg {
-                    micros - avg
-                } else {
-                    avg - micros
-                };
+                let diff = micros.abs_diff(avg);
 
                 // It is extremely unlikely for u128 to be overflown
                 diff.pow(2)
@@ -67,4 +63,8 @@ impl Stats {
             avg: from_micros(avg),
         })
     }
+
+    pub fn avg(&self) -> Duration {
+        self.avg
+    }
 }
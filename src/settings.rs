@@ -0,0 +1,207 @@
+use super::options::{OptionValue, Options};
+use super::Interval;
+
+use clap_verbosity_flag::Verbosity;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Defaults in the config file, or in one of its profiles.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+struct Section {
+    timeout: Option<OptionValue>,
+    verbosity: Option<String>,
+    ping: Options,
+    speed_test: Options,
+}
+
+/// `~/.config/ssh-utils/config.toml`, e.g.
+///
+/// ```toml
+/// timeout = 10
+/// verbosity = "warn"
+///
+/// [ping]
+/// interval = 0.5
+///
+/// [speed-test]
+/// duration = 5
+/// units = "bits"
+///
+/// [profiles.wan]
+/// timeout = 60
+/// speed-test = { duration = 30 }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    #[serde(flatten)]
+    defaults: Section,
+    profiles: BTreeMap<String, Section>,
+}
+
+/// Defaults with the selected profile applied.
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub timeout: Option<Interval>,
+    pub verbosity: Option<Verbosity>,
+    /// Options of ping, keyed by long name.
+    pub ping: Options,
+    /// Options of speed-test, keyed by long name.
+    pub speed_test: Options,
+}
+
+/// Parse level `off`, `error`, `warn`, `info`, `debug` or `trace`.
+pub fn parse_verbosity(s: &str) -> Result<Verbosity, String> {
+    let (verbose, quiet) = match &*s.to_ascii_lowercase() {
+        "off" => (0, 1),
+        "error" => (0, 0),
+        "warn" => (1, 0),
+        "info" => (2, 0),
+        "debug" => (3, 0),
+        "trace" => (4, 0),
+        _ => {
+            return Err(format!(
+                "Expected off, error, warn, info, debug or trace, found {s}"
+            ))
+        }
+    };
+    Ok(Verbosity::new(verbose, quiet))
+}
+
+impl Settings {
+    pub fn path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".config/ssh-utils/config.toml"))
+    }
+
+    /// Load the config file, or return empty settings if it does not exist.
+    pub fn load() -> io::Result<Self> {
+        let path = match Self::path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Self::default()),
+        };
+
+        let content = fs::read_to_string(&path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+        toml::from_str(&content).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {err}", path.display()),
+            )
+        })
+    }
+
+    /// Return the defaults overridden by `profile` if given.
+    pub fn profile(&self, profile: Option<&str>) -> Result<Profile, String> {
+        let mut section = self.defaults.clone();
+
+        if let Some(name) = profile {
+            let overrides = self
+                .profiles
+                .get(name)
+                .ok_or_else(|| format!("No profile {name} in config file"))?;
+
+            section.timeout = overrides.timeout.clone().or(section.timeout);
+            section.verbosity = overrides.verbosity.clone().or(section.verbosity);
+            section.ping.extend(overrides.ping.clone());
+            section.speed_test.extend(overrides.speed_test.clone());
+        }
+
+        Ok(Profile {
+            timeout: section
+                .timeout
+                .map(|timeout| {
+                    timeout
+                        .to_string()
+                        .parse()
+                        .map_err(|_| format!("Invalid timeout {timeout} in config file"))
+                })
+                .transpose()?,
+            verbosity: section
+                .verbosity
+                .map(|verbosity| parse_verbosity(&verbosity))
+                .transpose()?,
+            ping: section.ping,
+            speed_test: section.speed_test,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use log::Level;
+    use std::time::Duration;
+
+    const SETTINGS: &str = r#"
+timeout = 10
+verbosity = "warn"
+
+[ping]
+interval = 0.5
+count = 3
+
+[speed-test]
+duration = 5
+
+[profiles.wan]
+timeout = 60
+ping = { count = 10 }
+speed-test = { bytes = "1G" }
+
+[profiles.quiet]
+verbosity = "off"
+"#;
+
+    #[test]
+    fn test_profile() {
+        let settings: Settings = toml::from_str(SETTINGS).unwrap();
+
+        let profile = settings.profile(None).unwrap();
+        assert_eq!(profile.timeout.unwrap().0, Duration::from_secs(10));
+        assert_eq!(profile.verbosity.unwrap().log_level(), Some(Level::Warn));
+        assert_eq!(profile.ping["count"].to_string(), "3");
+        assert!(!profile.speed_test.contains_key("bytes"));
+
+        let profile = settings.profile(Some("wan")).unwrap();
+        assert_eq!(profile.timeout.unwrap().0, Duration::from_secs(60));
+        assert_eq!(profile.verbosity.unwrap().log_level(), Some(Level::Warn));
+        assert_eq!(profile.ping["count"].to_string(), "10");
+        assert_eq!(profile.ping["interval"].to_string(), "0.5");
+        assert_eq!(profile.speed_test["duration"].to_string(), "5");
+        assert_eq!(profile.speed_test["bytes"].to_string(), "1G");
+
+        let profile = settings.profile(Some("quiet")).unwrap();
+        assert_eq!(profile.timeout.unwrap().0, Duration::from_secs(10));
+        assert_eq!(profile.verbosity.unwrap().log_level(), None);
+
+        assert!(settings.profile(Some("lan")).is_err());
+    }
+
+    #[test]
+    fn test_profile_invalid() {
+        for content in ["timeout = \"soon\"", "verbosity = \"loud\""] {
+            let settings: Settings = toml::from_str(content).unwrap();
+            assert!(settings.profile(None).is_err(), "{content:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_verbosity() {
+        for (s, level) in [
+            ("off", None),
+            ("error", Some(Level::Error)),
+            ("Warn", Some(Level::Warn)),
+            ("info", Some(Level::Info)),
+            ("DEBUG", Some(Level::Debug)),
+            ("trace", Some(Level::Trace)),
+        ] {
+            assert_eq!(parse_verbosity(s).unwrap().log_level(), level, "{s:?}");
+        }
+        assert!(parse_verbosity("loud").is_err());
+    }
+}
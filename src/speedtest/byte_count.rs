@@ -0,0 +1,140 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Number of bytes, parsed from strings like "4096", "400K", "1G" or "1GiB".
+#[derive(Debug, Copy, Clone)]
+pub struct ByteCount(pub u64);
+
+impl ByteCount {
+    /// Parse with K, M, G and T being powers of 1024 with or without
+    /// the 'i', as usual for buffer sizes.
+    pub fn parse_binary(s: &str) -> Result<Self, String> {
+        Self::parse(s, 1024)
+    }
+
+    /// Parse with K, M, G and T being powers of `base`, or of 1024 if
+    /// followed by 'i'.
+    fn parse(s: &str, base: u64) -> Result<Self, String> {
+        let s = s.strip_suffix('B').unwrap_or(s);
+
+        let (s, base, binary) = match s.strip_suffix('i') {
+            Some(s) => (s, 1024, true),
+            None => (s, base, false),
+        };
+
+        let (num, exp) = match s.char_indices().last() {
+            Some((i, 'K' | 'k')) => (&s[..i], 1),
+            Some((i, 'M')) => (&s[..i], 2),
+            Some((i, 'G')) => (&s[..i], 3),
+            Some((i, 'T')) => (&s[..i], 4),
+            _ if binary => return Err(format!("Expected K, M, G or T before 'i' in {s}i")),
+            _ => (s, 0),
+        };
+
+        let num: u64 = num.parse().map_err(|err| format!("{err} in {s:?}"))?;
+        Ok(Self(num.saturating_mul(u64::pow(base, exp))))
+    }
+}
+
+impl FromStr for ByteCount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, 1000)
+    }
+}
+
+impl fmt::Display for ByteCount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Range of byte counts, parsed from strings like "4K..1M".
+#[derive(Debug, Copy, Clone)]
+pub struct ByteCountRange {
+    pub start: ByteCount,
+    pub end: ByteCount,
+}
+
+impl ByteCountRange {
+    /// Parse with K, M, G and T being powers of 1024, like
+    /// [`ByteCount::parse_binary`].
+    pub fn parse_binary(s: &str) -> Result<Self, String> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| format!("Expected <start>..<end>, found {s}"))?;
+
+        let parse = ByteCount::parse_binary;
+
+        let (start, end) = (parse(start)?, parse(end)?);
+        if end.0 < start.0 {
+            return Err(format!("End {end} is less than start {start}"));
+        }
+
+        Ok(Self { start, end })
+    }
+    /// Return the powers of two multiple of `start` up to `end`, inclusive.
+    pub fn doubling(self) -> impl Iterator<Item = ByteCount> {
+        let end = self.end.0;
+
+        std::iter::successors(Some(self.start.0.max(1)), |n| n.checked_mul(2))
+            .take_while(move |n| *n <= end)
+            .map(ByteCount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<u64, String> {
+        s.parse::<ByteCount>().map(|bytes| bytes.0)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("4096").unwrap(), 4096);
+        assert_eq!(parse("400K").unwrap(), 400_000);
+        assert_eq!(parse("400k").unwrap(), 400_000);
+        assert_eq!(parse("2M").unwrap(), 2_000_000);
+        assert_eq!(parse("1G").unwrap(), 1_000_000_000);
+        assert_eq!(parse("1GB").unwrap(), 1_000_000_000);
+        assert_eq!(parse("1T").unwrap(), 1_000_000_000_000);
+        assert_eq!(parse("4Ki").unwrap(), 4096);
+        assert_eq!(parse("1GiB").unwrap(), 1 << 30);
+        assert_eq!(parse("100B").unwrap(), 100);
+
+        assert!(parse("1i").is_err());
+        assert!(parse("1.5G").is_err());
+    }
+
+    #[test]
+    fn test_parse_binary() {
+        let parse = |s| ByteCount::parse_binary(s).map(|bytes| bytes.0);
+        assert_eq!(parse("4096").unwrap(), 4096);
+        assert_eq!(parse("4K").unwrap(), 4096);
+        assert_eq!(parse("4Ki").unwrap(), 4096);
+        assert_eq!(parse("1MB").unwrap(), 1 << 20);
+        assert!(parse("1i").is_err());
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let sizes = |s| -> Vec<u64> {
+            ByteCountRange::parse_binary(s)
+                .unwrap()
+                .doubling()
+                .map(|bytes| bytes.0)
+                .collect()
+        };
+        assert_eq!(sizes("4K..32K"), [4096, 8192, 16384, 32768]);
+        assert_eq!(sizes("4K..1M").last(), Some(&(1 << 20)));
+        assert_eq!(sizes("4Ki..40Ki"), [4096, 8192, 16384, 32768]);
+        assert_eq!(sizes("1K..1K"), [1024]);
+        assert_eq!(sizes("0..2"), [1, 2]);
+
+        assert!(ByteCountRange::parse_binary("4K").is_err());
+        assert!(ByteCountRange::parse_binary("1M..4K").is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use super::transfer::Direction;
+use super::{println_if_not_quiet, println_on_level, Level};
+
+use clap_verbosity_flag::Verbosity;
+use md5::Md5;
+use openssh::{Error, Session, Stdio};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+use std::io;
+
+/// Check that the whole `expected` bytes are received on download, the
+/// remote source writes less if e.g. the payload file is shorter.
+pub fn check_received(received: u64, expected: u64, label: &str) -> Result<(), Error> {
+    if received < expected {
+        Err(Error::Remote(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "{label}download received only {received} of {expected} bytes, \
+                 is the payload missing or shorter than --bytes?"
+            ),
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Hash algorithm used to verify the data transferred.
+#[derive(Debug, Copy, Clone)]
+pub enum Checksum {
+    Sha256,
+    Md5,
+}
+
+impl Checksum {
+    /// Return the first algorithm whose program is available on remote.
+    pub async fn probe(session: &Session, verbose: &Verbosity) -> Result<Self, Error> {
+        for checksum in [Checksum::Sha256, Checksum::Md5] {
+            let program = checksum.program();
+            println_on_level!(*verbose, Level::Debug, "Probing {program} on remote");
+
+            let status = session
+                .shell(format!("{program} < /dev/null"))
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .await?;
+
+            if status.success() {
+                return Ok(checksum);
+            }
+        }
+
+        Err(Error::Remote(io::Error::new(
+            io::ErrorKind::NotFound,
+            "Neither sha256sum nor md5sum is available on remote",
+        )))
+    }
+
+    /// Program on remote that prints the hash of its stdin.
+    pub fn program(self) -> &'static str {
+        match self {
+            Checksum::Sha256 => "sha256sum",
+            Checksum::Md5 => "md5sum",
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Checksum::Sha256 => Hasher::Sha256(Sha256::new()),
+            Checksum::Md5 => Hasher::Md5(Md5::new()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Hasher {
+    Sha256(Sha256),
+    Md5(Md5),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Md5(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Return the hash in hex, as printed by the remote program.
+    pub fn finalize(self) -> String {
+        let hash = match self {
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+        };
+
+        hash.iter().fold(String::new(), |mut hex, byte| {
+            write!(hex, "{byte:02x}").unwrap();
+            hex
+        })
+    }
+
+    /// Compare the hash with `output` of the remote program.
+    pub fn verify(
+        self,
+        output: &[u8],
+        label: &str,
+        direction: Direction,
+        verbose: &Verbosity,
+    ) -> Result<(), Error> {
+        let local = self.finalize();
+        let output = String::from_utf8_lossy(output);
+        // The line of the hash is "<hash>  -", ignore any other line.
+        let remote = output
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .find(|token| {
+                token.len() == local.len() && token.chars().all(|c| c.is_ascii_hexdigit())
+            })
+            .ok_or_else(|| {
+                Error::ChildIo(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{label}{direction} has no checksum in remote output: {output:?}"),
+                ))
+            })?;
+
+        if local == remote {
+            println_if_not_quiet!(*verbose, "{label}{direction} verified: {local}");
+            Ok(())
+        } else {
+            Err(Error::ChildIo(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{label}{direction} checksum mismatch: local {local}, remote {remote}"),
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256_ABC: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const MD5_ABC: &str = "900150983cd24fb0d6963f7d28e17f72";
+
+    fn verify(checksum: Checksum, output: &str) -> Result<(), Error> {
+        let mut hasher = checksum.hasher();
+        hasher.update(b"a");
+        hasher.update(b"bc");
+        hasher.verify(
+            output.as_bytes(),
+            "",
+            Direction::Download,
+            &Verbosity::new(0, 1),
+        )
+    }
+
+    #[test]
+    fn test_finalize() {
+        let mut hasher = Checksum::Sha256.hasher();
+        hasher.update(b"abc");
+        assert_eq!(hasher.finalize(), SHA256_ABC);
+
+        let mut hasher = Checksum::Md5.hasher();
+        hasher.update(b"abc");
+        assert_eq!(hasher.finalize(), MD5_ABC);
+    }
+
+    #[test]
+    fn test_verify() {
+        verify(Checksum::Sha256, &format!("{SHA256_ABC}  -\n")).unwrap();
+        verify(Checksum::Md5, &format!("{MD5_ABC}  -\n")).unwrap();
+
+        // Other lines, e.g. the stats of dd, are ignored.
+        let output = format!("1+0 records in\n0 bytes copied\n{MD5_ABC}  -\n");
+        verify(Checksum::Md5, &output).unwrap();
+    }
+
+    #[test]
+    fn test_verify_mismatch() {
+        let other = SHA256_ABC.replace('b', "c");
+        assert!(verify(Checksum::Sha256, &format!("{other}  -\n")).is_err());
+
+        // A hash of another length is not taken for the checksum.
+        assert!(verify(Checksum::Sha256, &format!("{MD5_ABC}  -\n")).is_err());
+        assert!(verify(Checksum::Sha256, "").is_err());
+        assert!(verify(Checksum::Md5, "sh: md5sum: not found\n").is_err());
+    }
+
+    #[test]
+    fn test_check_received() {
+        check_received(100, 100, "").unwrap();
+        match check_received(99, 100, "") {
+            Err(Error::Remote(err)) => assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof),
+            res => panic!("Unexpected {res:?}"),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use super::human_readable_unit::Format;
+use super::transfer::{Direction, Transfer};
+use super::{println_on_level, run_once, Destination, Level, SpeedTestArgs, SshSessionBuilder};
+
+use clap_verbosity_flag::Verbosity;
+use openssh::Error;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+/// One configuration of the speed test and the ssh connection to run with.
+#[derive(Debug)]
+pub struct Variant<'a> {
+    pub name: String,
+    pub args: SpeedTestArgs,
+    pub builder: SshSessionBuilder<'a>,
+    /// Config file used by `builder`, which must be kept alive until connected.
+    pub _config: Option<NamedTempFile>,
+}
+
+impl<'a> Variant<'a> {
+    pub fn new(name: String, args: SpeedTestArgs, builder: SshSessionBuilder<'a>) -> Self {
+        Self {
+            name,
+            args,
+            builder,
+            _config: None,
+        }
+    }
+}
+
+/// Run the speed test with every variant, then print a table of the speed
+/// and local CPU time of each variant, which is the CPU time of this
+/// process and of the ssh multiplex masters doing the encryption.
+///
+/// Failure of one variant (e.g. an algorithm unsupported by the remote)
+/// does not stop the others from running.
+pub async fn run_variants(
+    verbose: &Verbosity,
+    dest: &Destination,
+    variants: Vec<Variant<'_>>,
+) -> Result<(), Error> {
+    let mut results = Vec::with_capacity(variants.len());
+    let mut cpu_times = Vec::with_capacity(variants.len());
+
+    for variant in &variants {
+        println!("--- {} ---", variant.name);
+
+        let res = run_once(&variant.args, verbose, &variant.builder).await;
+        cpu_times.push(match &res {
+            Ok((_, Some(local_cpu))) => Some(local_cpu.total()),
+            _ => None,
+        });
+        let res = res.map(|(result, _)| result);
+
+        if let Err(err) = &res {
+            println_on_level!(
+                *verbose,
+                Level::Error,
+                "Failed to run speed test with {}: {err}",
+                variant.name
+            );
+        }
+        results.push(res);
+    }
+
+    let directions: Vec<Direction> = results
+        .iter()
+        .flatten()
+        .next()
+        .map(|result| result.iter().map(|(direction, _)| *direction).collect())
+        .unwrap_or_default();
+
+    let width = variants
+        .iter()
+        .map(|variant| variant.name.len())
+        .max()
+        .unwrap_or(0);
+
+    println!("--- {dest} speed comparison ---");
+
+    print!("{:<width$}", "");
+    for direction in &directions {
+        print!("  {direction:>14}");
+    }
+    println!("  {:>10}", "CPU time");
+
+    for ((variant, res), cpu_time) in variants.iter().zip(&results).zip(&cpu_times) {
+        print!("{:<width$}", variant.name);
+        match res {
+            Ok(result) => {
+                for (_, transfer) in result {
+                    print!("  {:>14}", format_rate(transfer, variant.args.format()));
+                }
+            }
+            Err(_) => {
+                for _ in 0..directions.len().max(1) {
+                    print!("  {:>14}", "failed");
+                }
+            }
+        }
+        println!("  {:>10}", format_cpu_time(*cpu_time));
+    }
+
+    if results.iter().all(Result::is_err) {
+        results.pop().unwrap_or(Ok(Vec::new())).map(|_| ())
+    } else {
+        Ok(())
+    }
+}
+
+fn format_rate(transfer: &Transfer, format: Format) -> String {
+    format!("{}/s", transfer.rate(format))
+}
+
+fn format_cpu_time(cpu_time: Option<Duration>) -> String {
+    match cpu_time {
+        Some(cpu_time) => format!("{:.3}s", cpu_time.as_secs_f64()),
+        None => "unknown".to_string(),
+    }
+}
@@ -0,0 +1,387 @@
+use super::{println_on_level, Destination, Level};
+
+use clap_verbosity_flag::Verbosity;
+use openssh::{Error, Session, Stdio};
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+
+/// Interval of sampling the local CPU time during a speed test.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+fn rusage(who: libc::c_int) -> io::Result<Duration> {
+    let mut usage = MaybeUninit::<libc::rusage>::uninit();
+
+    // Safety: getrusage only writes to usage.
+    if unsafe { libc::getrusage(who, usage.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Safety: usage is initialized on success.
+    let usage = unsafe { usage.assume_init() };
+
+    let to_duration = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    Ok(to_duration(usage.ru_utime) + to_duration(usage.ru_stime))
+}
+
+/// Return the pid of the ssh multiplex master listening on `ctl`, parsed
+/// from "Master running (pid=N)" printed by `ssh -O check`.
+async fn master_pid(ctl: &Path) -> io::Result<u32> {
+    let output = Command::new("ssh")
+        .args(["-O", "check", "-S"])
+        .arg(ctl)
+        .arg("ssh-utils")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .output()
+        .await?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    stderr
+        .split_once("pid=")
+        .and_then(|(_, rest)| rest.split(')').next()?.parse().ok())
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "Failed to get the pid of ssh multiplex master: {}",
+                stderr.trim()
+            ))
+        })
+}
+
+/// Return the user and system CPU time consumed by process `pid`, read
+/// from /proc/<pid>/stat.
+fn process_cpu_time(pid: u32) -> io::Result<Duration> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat"))?;
+
+    // Fields after the command, which is in parentheses and can contain
+    // spaces, start from the 3rd field state, so utime and stime (the
+    // 14th and 15th fields) are the 12th and 13th.
+    let ticks: Option<u64> = stat.rsplit_once(')').and_then(|(_, fields)| {
+        let mut fields = fields.split_whitespace().skip(11);
+        let utime: u64 = fields.next()?.parse().ok()?;
+        let stime: u64 = fields.next()?.parse().ok()?;
+        Some(utime + stime)
+    });
+    let ticks = ticks.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Failed to parse /proc/{pid}/stat"),
+        )
+    })?;
+
+    // Safety: sysconf has no side effect.
+    let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64;
+    Ok(Duration::from_nanos(ticks * 1_000_000_000 / ticks_per_sec))
+}
+
+/// CPU time of this process and of the ssh multiplex masters at `at`.
+#[derive(Debug, Copy, Clone)]
+struct Sample {
+    at: Instant,
+    this: Duration,
+    masters: Duration,
+}
+
+impl Sample {
+    fn take(pids: &[u32]) -> io::Result<Self> {
+        let mut masters = Duration::ZERO;
+        for pid in pids {
+            masters += process_cpu_time(*pid)?;
+        }
+
+        Ok(Self {
+            at: Instant::now(),
+            this: rusage(libc::RUSAGE_SELF)?,
+            masters,
+        })
+    }
+
+    fn total(&self) -> Duration {
+        self.this + self.masters
+    }
+}
+
+/// Local CPU time consumed during a speed test.
+#[derive(Debug, Copy, Clone)]
+pub struct LocalCpu {
+    /// CPU time of this process.
+    pub this: Duration,
+    /// CPU time of the ssh multiplex masters, which do the encryption.
+    pub masters: Duration,
+    pub elapsed: Duration,
+    /// Highest usage between two samples, in percent of one CPU.
+    pub peak: f64,
+}
+
+impl LocalCpu {
+    pub fn total(&self) -> Duration {
+        self.this + self.masters
+    }
+
+    fn percent(cpu_time: Duration, elapsed: Duration) -> f64 {
+        cpu_time.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON) * 100.0
+    }
+}
+
+/// Samples the CPU time of this process and of the ssh multiplex masters
+/// of the sessions every [`SAMPLE_INTERVAL`] in background.
+#[derive(Debug)]
+struct LocalCpuSampler {
+    pids: Vec<u32>,
+    samples: Arc<Mutex<Vec<Sample>>>,
+    task: JoinHandle<()>,
+}
+
+impl LocalCpuSampler {
+    async fn start(sessions: &[Session]) -> io::Result<Self> {
+        let mut pids = Vec::with_capacity(sessions.len());
+        for session in sessions {
+            pids.push(master_pid(session.control_socket()).await?);
+        }
+
+        let samples = Arc::new(Mutex::new(vec![Sample::take(&pids)?]));
+        let task = tokio::spawn({
+            let (pids, samples) = (pids.clone(), samples.clone());
+            async move {
+                let mut interval = interval(SAMPLE_INTERVAL);
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                interval.tick().await;
+
+                loop {
+                    interval.tick().await;
+                    match Sample::take(&pids) {
+                        Ok(sample) => samples.lock().unwrap().push(sample),
+                        Err(_) => break,
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            pids,
+            samples,
+            task,
+        })
+    }
+
+    fn stop(self) -> LocalCpu {
+        self.task.abort();
+
+        let mut samples = self.samples.lock().unwrap();
+        if let Ok(sample) = Sample::take(&self.pids) {
+            samples.push(sample);
+        }
+
+        let (first, last) = (samples[0], samples[samples.len() - 1]);
+        let peak = samples
+            .windows(2)
+            .map(|pair| {
+                LocalCpu::percent(
+                    pair[1].total().saturating_sub(pair[0].total()),
+                    pair[1].at - pair[0].at,
+                )
+            })
+            .fold(0.0, f64::max);
+
+        LocalCpu {
+            this: last.this.saturating_sub(first.this),
+            masters: last.masters.saturating_sub(first.masters),
+            elapsed: last.at - first.at,
+            peak,
+        }
+    }
+}
+
+/// Aggregated CPU time of all cores, read from /proc/stat on remote.
+#[derive(Debug, Copy, Clone)]
+struct ProcStat {
+    user: u64,
+    system: u64,
+    idle: u64,
+    total: u64,
+    cpus: usize,
+}
+
+impl ProcStat {
+    fn parse(stat: &str) -> Option<Self> {
+        let mut lines = stat.lines();
+        let fields: Vec<u64> = lines
+            .next()?
+            .strip_prefix("cpu ")?
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .ok()?;
+
+        // user nice system idle iowait irq softirq steal ...
+        let field = |i: usize| fields.get(i).copied().unwrap_or(0);
+
+        Some(Self {
+            user: field(0) + field(1),
+            system: field(2) + field(5) + field(6),
+            idle: field(3) + field(4),
+            total: fields.iter().take(8).sum(),
+            cpus: lines.filter(|line| line.starts_with("cpu")).count(),
+        })
+    }
+
+    async fn read(session: &Session) -> Result<Self, Error> {
+        let output = session
+            .command("cat")
+            .arg("/proc/stat")
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .await?;
+
+        Self::parse(&String::from_utf8_lossy(&output.stdout)).ok_or_else(|| {
+            Error::Remote(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Failed to read /proc/stat on remote",
+            ))
+        })
+    }
+}
+
+/// CPU usage of this process, the ssh multiplex masters and remote
+/// during a speed test.
+#[derive(Debug)]
+pub struct CpuUsage {
+    local: io::Result<LocalCpuSampler>,
+    remote: Option<Result<ProcStat, Error>>,
+}
+
+impl CpuUsage {
+    /// Start sampling local CPU usage, and remote CPU usage if `remote`.
+    pub async fn start(sessions: &[Session], remote: bool) -> Self {
+        Self {
+            local: LocalCpuSampler::start(sessions).await,
+            remote: if remote {
+                Some(ProcStat::read(&sessions[0]).await)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Stop sampling and print CPU usage since `start` if `print`.
+    ///
+    /// Return the local CPU usage if it is measured.
+    pub async fn finish(
+        self,
+        session: &Session,
+        dest: &Destination,
+        verbose: &Verbosity,
+        print: bool,
+    ) -> Option<LocalCpu> {
+        let local = self.local.map(LocalCpuSampler::stop);
+        let remote = match self.remote {
+            Some(start) => Some((start, ProcStat::read(session).await)),
+            None => None,
+        };
+
+        if !print {
+            return local.ok();
+        }
+
+        println!("--- {dest} CPU usage ---");
+
+        match &local {
+            Ok(local) => println!(
+                "local:  {:.1}% of one CPU, peak {:.1}% (ssh-utils {:.1}%, ssh {:.1}%)",
+                LocalCpu::percent(local.total(), local.elapsed),
+                local.peak,
+                LocalCpu::percent(local.this, local.elapsed),
+                LocalCpu::percent(local.masters, local.elapsed),
+            ),
+            Err(err) => {
+                println_on_level!(*verbose, Level::Warn, "Failed to get local CPU time: {err}")
+            }
+        }
+
+        match remote {
+            Some((Ok(start), Ok(end))) => {
+                let total = end.total.saturating_sub(start.total).max(1) as f64;
+                let percent =
+                    |end: u64, start: u64| end.saturating_sub(start) as f64 / total * 100.0;
+
+                println!(
+                    "remote: {:.1}% of {} CPUs (user {:.1}%, system {:.1}%)",
+                    100.0 - percent(end.idle, start.idle),
+                    end.cpus,
+                    percent(end.user, start.user),
+                    percent(end.system, start.system)
+                );
+            }
+            Some((Err(err), _) | (_, Err(err))) => println_on_level!(
+                *verbose,
+                Level::Warn,
+                "Failed to get remote CPU usage: {err}"
+            ),
+            None => (),
+        }
+
+        local.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_proc_stat() {
+        let stat = ProcStat::parse(
+            "cpu  100 10 50 800 20 5 15 7 0 0\n\
+             cpu0 50 5 25 400 10 3 7 3 0 0\n\
+             cpu1 50 5 25 400 10 2 8 4 0 0\n\
+             intr 12345\n\
+             ctxt 678\n",
+        )
+        .unwrap();
+
+        assert_eq!(stat.user, 110);
+        assert_eq!(stat.system, 70);
+        assert_eq!(stat.idle, 820);
+        // guest and guest_nice are already counted in user and nice.
+        assert_eq!(stat.total, 1007);
+        assert_eq!(stat.cpus, 2);
+
+        // Old kernels have fewer fields.
+        let stat = ProcStat::parse("cpu 1 2 3 4\ncpu0 1 2 3 4\n").unwrap();
+        assert_eq!(
+            (stat.user, stat.system, stat.idle, stat.total),
+            (3, 3, 4, 10)
+        );
+
+        for stat in ["", "cpu0 1 2 3 4\n", "cpu 1 x 3 4\n"] {
+            assert!(ProcStat::parse(stat).is_none(), "{stat:?}");
+        }
+    }
+
+    #[test]
+    fn test_process_cpu_time() {
+        // Burn some CPU time, then both ways of reading it must agree.
+        let start = std::time::Instant::now();
+        while start.elapsed() < Duration::from_millis(200) {
+            std::hint::black_box(start.elapsed());
+        }
+
+        let from_proc = process_cpu_time(std::process::id()).unwrap();
+        let from_rusage = rusage(libc::RUSAGE_SELF).unwrap();
+        assert!(from_proc >= Duration::from_millis(150), "{from_proc:?}");
+        assert!(
+            from_proc.abs_diff(from_rusage) < Duration::from_millis(50),
+            "{from_proc:?} {from_rusage:?}"
+        );
+
+        assert!(process_cpu_time(u32::MAX).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+use super::human_readable_unit::{Format, HumanReadableUnit};
+use super::transfer::Transfer;
+use super::{println_on_level, Level};
+
+use clap_verbosity_flag::Verbosity;
+use openssh::{Error, Session, Stdio};
+use shell_escape::unix::escape;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+use tokio::time::Instant;
+
+const BLOCK_SIZE: u64 = 1024 * 1024;
+
+/// Return the seconds dd reports in its stats, e.g. "... copied, 1.5 s, ..."
+/// of GNU dd or "... transferred in 1.5 secs ..." of BSD dd.
+fn parse_dd_secs(stats: &str) -> Option<f64> {
+    stats.lines().find_map(|line| {
+        let (_, rest) = line
+            .split_once(" copied, ")
+            .or_else(|| line.split_once(" transferred in "))?;
+        rest.split_whitespace().next()?.parse().ok()
+    })
+}
+
+/// Run `command` on remote and return how long it takes, measured locally.
+async fn time(session: &Session, command: &str, verbose: &Verbosity) -> Result<Duration, Error> {
+    time_dd(session, command, Duration::ZERO, verbose).await
+}
+
+/// Run dd `command` on remote and return how long it takes as reported by
+/// dd, or measured locally minus `overhead` of executing a command on
+/// remote if dd does not report it (e.g. busybox).
+async fn time_dd(
+    session: &Session,
+    command: &str,
+    overhead: Duration,
+    verbose: &Verbosity,
+) -> Result<Duration, Error> {
+    println_on_level!(*verbose, Level::Debug, "Executing {command} on remote");
+
+    let start = Instant::now();
+    let output = session
+        .shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await?;
+    let elapsed = start.elapsed();
+
+    if !output.status.success() {
+        return Err(Error::Remote(io::Error::other(format!(
+            "Failed to execute {command} on remote: {:#?}",
+            output.status
+        ))));
+    }
+
+    match parse_dd_secs(&String::from_utf8_lossy(&output.stderr))
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+    {
+        Some(elapsed) => Ok(elapsed),
+        None => Ok(elapsed.saturating_sub(overhead)),
+    }
+}
+
+/// Return whether `command` succeeds on remote.
+async fn succeeds(session: &Session, command: &str) -> Result<bool, Error> {
+    Ok(session
+        .shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await?
+        .success())
+}
+
+/// Result of the disk test.
+#[derive(Debug)]
+pub struct DiskTest {
+    pub write: Transfer,
+    pub read: Transfer,
+    /// Whether the read bypasses the page cache.
+    pub cold: bool,
+}
+
+/// Measure sequential write (with fsync) and read speed of the remote
+/// filesystem by writing `bytes` to a temporary file in `dir` with dd,
+/// then reading it back.
+///
+/// The read is cold if dd supports O_DIRECT, or if `drop_caches` and the
+/// page cache of remote can be dropped (as root), otherwise it may be warm.
+pub async fn disk_test(
+    session: &Session,
+    dir: &Path,
+    bytes: u64,
+    drop_caches: bool,
+    verbose: &Verbosity,
+) -> Result<DiskTest, Error> {
+    let count = bytes.div_ceil(BLOCK_SIZE).max(1);
+    let bytes = count * BLOCK_SIZE;
+
+    let path = dir.join(format!(".ssh-utils-disk-{}", process::id()));
+    let path = escape(path.to_string_lossy());
+
+    let res = async {
+        let overhead = time(session, "true", verbose).await?;
+
+        let write = format!("dd if=/dev/zero of={path} bs={BLOCK_SIZE} count={count} conv=fsync");
+        let write = time_dd(session, &write, overhead, verbose).await?;
+
+        let read = format!("dd if={path} of=/dev/null bs={BLOCK_SIZE}");
+        let direct = format!("{read} iflag=direct");
+        let (read, cold) = match time_dd(session, &direct, overhead, verbose).await {
+            Ok(read) => (read, true),
+            Err(_) => {
+                let cold = drop_caches
+                    && succeeds(session, "sync && echo 3 > /proc/sys/vm/drop_caches").await?;
+                if drop_caches && !cold {
+                    println_on_level!(
+                        *verbose,
+                        Level::Warn,
+                        "Failed to drop page cache on remote, are you root?"
+                    );
+                }
+                (time_dd(session, &read, overhead, verbose).await?, cold)
+            }
+        };
+
+        Ok(DiskTest {
+            write: Transfer {
+                bytes,
+                elapsed: write,
+            },
+            read: Transfer {
+                bytes,
+                elapsed: read,
+            },
+            cold,
+        })
+    }
+    .await;
+
+    if !succeeds(session, &format!("rm -f {path}")).await? {
+        println_on_level!(*verbose, Level::Warn, "Failed to remove {path} on remote");
+    }
+
+    res
+}
+
+impl DiskTest {
+    pub fn print(&self, dir: &Path, format: Format) {
+        println!("--- {} disk ---", dir.display());
+        for (transfer, verb, noun) in [
+            (&self.write, "written", "write"),
+            (&self.read, "read", "read"),
+        ] {
+            println!(
+                "{} is {verb} in {:#?}, {noun} speed = {}/s",
+                HumanReadableUnit::new(transfer.bytes, format),
+                transfer.elapsed,
+                transfer.rate(format)
+            );
+        }
+        if !self.cold {
+            println!("read may be warm: dd does not support O_DIRECT, pass --drop-caches as root");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_dd_secs() {
+        let gnu = "2+0 records in\n2+0 records out\n\
+                   2097152 bytes (2.1 MB, 2.0 MiB) copied, 0.00251 s, 836 MB/s\n";
+        assert_eq!(parse_dd_secs(gnu), Some(0.00251));
+
+        let bsd = "2+0 records in\n2+0 records out\n\
+                   2097152 bytes transferred in 0.004 secs (524288000 bytes/sec)\n";
+        assert_eq!(parse_dd_secs(bsd), Some(0.004));
+
+        assert_eq!(parse_dd_secs("2+0 records in\n2+0 records out\n"), None);
+    }
+}
@@ -1,91 +1,119 @@
-use super::human_readable_unit::HumanReadableUnit;
-use super::{println_on_level, Level};
+use super::checksum::{check_received, Hasher};
+use super::ramp::Ramp;
+use super::transfer::{Direction, Measurement, Reporter, Transfer};
+use super::{println_on_level, Level, SpeedTestArgs, Tools};
 
 use clap_verbosity_flag::Verbosity;
-use openssh::{ChildStdout, Error, Session, Stdio};
-use std::time::{Duration, Instant};
-use tokio::io::AsyncReadExt;
+use openssh::{Error, Session, Stdio};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio::signal::ctrl_c;
-use tokio::time::{interval, MissedTickBehavior};
+use tokio::time::{sleep_until, Instant};
 
-async fn download(
-    child_stdout: &mut ChildStdout,
+async fn download<R: AsyncRead + Unpin>(
+    reader: &mut R,
     n: &mut u64,
+    limit: u64,
     buffer: &mut [u8],
+    hasher: &mut Option<Hasher>,
     verbose: Verbosity,
-) -> Result<(), Error> {
-    loop {
+) -> Result<bool, Error> {
+    while *n < limit {
+        let remaining: usize = (limit - *n).try_into().unwrap_or(usize::MAX);
+        let len = buffer.len().min(remaining);
+
         println_on_level!(verbose, Level::Debug, "Downloading");
-        let cnt = child_stdout.read(buffer).await.map_err(Error::ChildIo)?;
+        let cnt = reader
+            .read(&mut buffer[..len])
+            .await
+            .map_err(Error::ChildIo)?;
+        if let Some(hasher) = hasher {
+            hasher.update(&buffer[..cnt]);
+        }
+
         let cnt: u64 = cnt.try_into().unwrap();
 
         if cnt == 0 {
-            // break on EOF
-            break Ok(());
+            // return on EOF
+            return Ok(true);
         }
 
         *n += cnt;
     }
+
+    Ok(false)
 }
 
-pub async fn speedtest_download(verbose: Verbosity, session: &Session) -> Result<(), Error> {
-    let mut interval = interval(Duration::from_secs(5));
-    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+pub async fn speedtest_download(
+    args: &SpeedTestArgs,
+    verbose: Verbosity,
+    session: &Session,
+    label: &str,
+    tools: Tools,
+) -> Result<Transfer, Error> {
+    let mut hasher = tools.checksum.map(|checksum| checksum.hasher());
+
+    // When verifying, remote writes exactly byte_limit bytes, so read until EOF.
+    let (command, limit) = match tools.checksum {
+        Some(checksum) => (
+            tools
+                .source
+                .verified_command(&args.payload, args.byte_limit(), checksum),
+            u64::MAX,
+        ),
+        None => (
+            tools
+                .source
+                .command(&args.payload, args.bytes.map(|bytes| bytes.0)),
+            args.byte_limit(),
+        ),
+    };
 
-    println_on_level!(verbose, Level::Debug, "Spawning process seq on remote");
+    println_on_level!(verbose, Level::Debug, "Spawning {command} on remote");
     let mut child = session
-        .command("seq")
-        .arg("0")
-        .arg("1e9")
+        .shell(&command)
         .stdin(Stdio::null())
         .stdout(Stdio::piped())
-        .stderr(Stdio::null())
+        .stderr(if hasher.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
         .spawn()
         .await?;
 
     let mut child_stdout = child.stdout().take().unwrap();
-    let mut n = 0;
-
-    // Buffer is 4096 bytes large, the size of pipe.
-    let mut buffer: Vec<u8> = vec![0; 4096];
-
-    let shutdown_requested = ctrl_c();
-    tokio::pin!(shutdown_requested);
-
-    let instant = Instant::now();
-
-    while n < 4096 * 100 {
-        tokio::select! {
-            res = download(&mut child_stdout, &mut n, &mut buffer, verbose.clone()) => {
-                res?;
-                // break on EOF
-                break
-            },
-            _ = interval.tick() => (),
-            _ = &mut shutdown_requested => {
-                println_on_level!(verbose, Level::Debug, "Ctrl C signal received");
-                break
-            }
-        }
-    }
-    let elapsed = instant.elapsed();
+    let child_stderr = child.stderr().take();
+    let (n, measurement, eof) =
+        download_until_stopped(args, &verbose, &mut child_stdout, limit, label, &mut hasher)
+            .await?;
+    let transfer = measurement.finish(n);
     drop(child_stdout);
 
-    println!(
-        "{} is downloaded in {elapsed:#?}, download speed = {}/s",
-        HumanReadableUnit::new(n),
-        HumanReadableUnit::new(n / elapsed.as_secs())
-    );
+    let mut output = Vec::new();
+    if let Some(mut child_stderr) = child_stderr {
+        child_stderr
+            .read_to_end(&mut output)
+            .await
+            .map_err(Error::ChildIo)?;
+    }
 
-    // Wait for remote process seq
+    // Wait for remote process
     match child.wait().await {
         Ok(exit_status) => {
             if !exit_status.success() {
-                println_on_level!(
-                    verbose,
-                    Level::Error,
-                    "Failed to execute seq on remote: {exit_status:#?}"
-                );
+                if eof {
+                    println_on_level!(
+                        verbose,
+                        Level::Error,
+                        "Failed to execute {command} on remote: {exit_status:#?}"
+                    );
+                } else {
+                    println_on_level!(
+                        verbose,
+                        Level::Debug,
+                        "remote process {command} exited with {exit_status:#?} due to child_stdout closed"
+                    );
+                }
             }
         }
 
@@ -93,12 +121,76 @@ pub async fn speedtest_download(verbose: Verbosity, session: &Session) -> Result
             println_on_level!(
                 verbose,
                 Level::Debug,
-                "remote process seq terminated due to child_stdout closed"
+                "remote process {command} terminated due to child_stdout closed"
             )
         }
 
         Err(err) => return Err(err),
     };
 
-    Ok(())
+    match hasher {
+        Some(hasher) if eof => {
+            check_received(n, args.byte_limit(), label)?;
+            hasher.verify(&output, label, Direction::Download, &verbose)?
+        }
+        Some(_) => println_on_level!(
+            verbose,
+            Level::Warn,
+            "{label}download is not verified since it is stopped before EOF"
+        ),
+        None => (),
+    }
+
+    Ok(transfer)
+}
+
+/// Download from `reader` until EOF, `limit` or the deadline is reached,
+/// or ctrl_c is received.
+///
+/// Return the number of bytes downloaded and whether EOF is reached.
+pub async fn download_until_stopped<R: AsyncRead + Unpin>(
+    args: &SpeedTestArgs,
+    verbose: &Verbosity,
+    reader: &mut R,
+    limit: u64,
+    label: &str,
+    hasher: &mut Option<Hasher>,
+) -> Result<(u64, Measurement, bool), Error> {
+    let mut n = 0;
+
+    let mut buffer: Vec<u8> = vec![0; args.buffer_size()];
+
+    let shutdown_requested = ctrl_c();
+    tokio::pin!(shutdown_requested);
+
+    let instant = Instant::now();
+    let deadline = args.deadline(instant);
+    let mut measurement = Measurement::new(instant, args.omit);
+    let mut reporter = Reporter::new(label, instant, args.report_interval, args.format());
+    let mut ramp = Ramp::new(instant, args.ramp);
+    let mut eof = false;
+
+    loop {
+        tokio::select! {
+            res = download(reader, &mut n, limit, &mut buffer, hasher, verbose.clone()) => {
+                eof = res?;
+                // break on EOF or reaching the byte limit
+                break
+            },
+            _ = reporter.tick() => reporter.report(verbose, n, &measurement),
+            _ = ramp.tick() => ramp.sample(n),
+            _ = sleep_until(measurement.omit_until), if measurement.omitted.is_none() => {
+                measurement.end_warm_up(n)
+            },
+            _ = sleep_until(deadline.unwrap_or(instant)), if deadline.is_some() => break,
+            _ = &mut shutdown_requested => {
+                println_on_level!(*verbose, Level::Debug, "Ctrl C signal received");
+                break
+            }
+        }
+    }
+
+    ramp.print(verbose, label, n, args.format());
+
+    Ok((n, measurement, eof))
 }
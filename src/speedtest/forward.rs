@@ -0,0 +1,222 @@
+use super::checksum::check_received;
+use super::download::download_until_stopped;
+use super::transfer::{Direction, Transfer};
+use super::upload::upload_until_stopped;
+use super::{println_on_level, Level, SpeedTestArgs, Tools};
+
+use clap_verbosity_flag::Verbosity;
+use openssh::{Error, ForwardType, Session, Stdio};
+use shell_escape::unix::escape;
+use std::io;
+use std::net::Ipv4Addr;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+/// Python script run on remote that listens on a random port of localhost,
+/// prints "relay port <port>" to stderr and then relays a single connection
+/// to stdout ("recv") or stdin to the connection ("send").
+const RELAY: &str = r#"
+import shutil, socket, sys
+server = socket.socket()
+server.bind(("127.0.0.1", 0))
+server.listen(1)
+server.settimeout(30)
+print("relay port", server.getsockname()[1], file=sys.stderr, flush=True)
+conn = server.accept()[0]
+conn.settimeout(None)
+server.close()
+if sys.argv[1] == "recv":
+    shutil.copyfileobj(conn.makefile("rb"), sys.stdout.buffer, 65536)
+else:
+    stream = conn.makefile("wb")
+    shutil.copyfileobj(sys.stdin.buffer, stream, 65536)
+    stream.flush()
+    conn.shutdown(socket.SHUT_WR)
+"#;
+
+const PORT_PREFIX: &str = "relay port ";
+
+fn relay(mode: &str) -> String {
+    format!("python3 -c {} {mode}", escape(RELAY.into()))
+}
+
+/// Check that python3, which runs the relay, is available on remote.
+pub async fn probe(session: &Session, verbose: &Verbosity) -> Result<(), Error> {
+    println_on_level!(*verbose, Level::Debug, "Probing python3 on remote");
+
+    let status = session
+        .shell("python3 -c ''")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::Remote(io::Error::new(
+            io::ErrorKind::NotFound,
+            "python3 is required on remote by --transport forward",
+        )))
+    }
+}
+
+/// Run speed test in `direction` over a local port forward, through which
+/// data is relayed to/from a process on remote.
+///
+/// Both directions use a local forward, with the relay on remote listening,
+/// instead of a remote forward for download.
+///
+/// The forward listens on a unix socket in a temporary directory, which is
+/// removed afterwards. The forward itself cannot be closed and stays on the
+/// ssh multiplex master until it exits.
+pub async fn speedtest_forward(
+    args: &SpeedTestArgs,
+    verbose: Verbosity,
+    session: &Session,
+    label: &str,
+    direction: Direction,
+    tools: Tools,
+) -> Result<Transfer, Error> {
+    let mut hasher = tools.checksum.map(|checksum| checksum.hasher());
+
+    // The hash is written to stdout on upload and to stderr on download.
+    let command = match (direction, tools.checksum) {
+        (Direction::Upload, Some(checksum)) => {
+            format!("{} | {}", relay("recv"), checksum.program())
+        }
+        (Direction::Upload, None) => format!("{} | dd of=/dev/null 2>/dev/null", relay("recv")),
+        (Direction::Download, Some(checksum)) => format!(
+            "({}) | {}",
+            tools
+                .source
+                .verified_command(&args.payload, args.byte_limit(), checksum),
+            relay("send")
+        ),
+        (Direction::Download, None) => format!(
+            "{} | {}",
+            tools
+                .source
+                .command(&args.payload, args.bytes.map(|bytes| bytes.0)),
+            relay("send")
+        ),
+    };
+
+    println_on_level!(verbose, Level::Debug, "Spawning {command} on remote");
+    let mut child = session
+        .shell(&command)
+        .stdin(Stdio::null())
+        .stdout(if direction == Direction::Upload && hasher.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stderr(Stdio::piped())
+        .spawn()
+        .await?;
+
+    // Other lines before the port come from the checksum of a short download.
+    let mut child_stderr = BufReader::new(child.stderr().take().unwrap());
+    let mut output = Vec::new();
+    let port = loop {
+        let mut line = String::new();
+        let cnt = child_stderr
+            .read_line(&mut line)
+            .await
+            .map_err(Error::ChildIo)?;
+        if cnt == 0 {
+            return Err(Error::Remote(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Failed to start the relay on remote, is python3 available?",
+            )));
+        }
+
+        match line.trim().strip_prefix(PORT_PREFIX).map(str::parse::<u16>) {
+            Some(Ok(port)) => break port,
+            _ => output.extend_from_slice(line.as_bytes()),
+        }
+    };
+
+    let dir = tempfile::Builder::new()
+        .prefix("ssh-utils-forward")
+        .tempdir()
+        .map_err(Error::ChildIo)?;
+    let path = dir.path().join("socket");
+
+    println_on_level!(
+        verbose,
+        Level::Debug,
+        "Forwarding {} to remote port {port}",
+        path.display()
+    );
+    session
+        .request_port_forward(
+            ForwardType::Local,
+            path.as_path(),
+            (Ipv4Addr::LOCALHOST, port),
+        )
+        .await?;
+    let mut stream = UnixStream::connect(&path).await.map_err(Error::ChildIo)?;
+
+    let (transfer, complete) = match direction {
+        Direction::Upload => {
+            let (n, measurement) =
+                upload_until_stopped(args, &verbose, &mut stream, label, &mut hasher).await?;
+            stream.shutdown().await.map_err(Error::ChildIo)?;
+            drop(stream);
+
+            (measurement.finish(n), true)
+        }
+        Direction::Download => {
+            let limit = if hasher.is_some() {
+                u64::MAX
+            } else {
+                args.byte_limit()
+            };
+            let (n, measurement, eof) =
+                download_until_stopped(args, &verbose, &mut stream, limit, label, &mut hasher)
+                    .await?;
+            let transfer = measurement.finish(n);
+            drop(stream);
+
+            if hasher.is_some() && eof {
+                check_received(n, args.byte_limit(), label)?;
+            }
+
+            (transfer, eof)
+        }
+    };
+
+    match child.stdout().take() {
+        Some(mut child_stdout) => child_stdout.read_to_end(&mut output).await,
+        None => child_stderr.read_to_end(&mut output).await,
+    }
+    .map_err(Error::ChildIo)?;
+
+    // Wait for remote process
+    match child.wait().await {
+        Ok(exit_status) if !exit_status.success() => {
+            println_on_level!(
+                verbose,
+                if complete { Level::Error } else { Level::Debug },
+                "remote process {command} exited with {exit_status:#?}"
+            );
+        }
+        Ok(_) => (),
+        Err(Error::RemoteProcessTerminated) if !complete => (),
+        Err(err) => return Err(err),
+    }
+
+    match hasher {
+        Some(hasher) if complete => hasher.verify(&output, label, direction, &verbose)?,
+        Some(_) => println_on_level!(
+            verbose,
+            Level::Warn,
+            "{label}{direction} is not verified since it is stopped before EOF"
+        ),
+        None => (),
+    }
+
+    Ok(transfer)
+}
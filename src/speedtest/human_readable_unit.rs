@@ -1,25 +1,97 @@
 use std::fmt;
+use std::str::FromStr;
 
+/// Unit sizes and rates are printed in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Units {
+    Bits,
+    Bytes,
+}
+
+impl FromStr for Units {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bits" => Ok(Units::Bits),
+            "bytes" => Ok(Units::Bytes),
+            s => Err(format!("Expected bits or bytes, found {s}")),
+        }
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Units::Bits => "bits",
+            Units::Bytes => "bytes",
+        })
+    }
+}
+
+/// Format of [`HumanReadableUnit`]: bytes or bits, with SI (K = 1000)
+/// or IEC (Ki = 1024) prefix.
 #[derive(Debug, Copy, Clone)]
-pub struct HumanReadableUnit(u64);
+pub struct Format {
+    pub units: Units,
+    pub iec: bool,
+}
+
+/// Number of bytes, printed with an SI (K = 1000) or IEC (Ki = 1024)
+/// prefix, in bytes or bits, with 2 decimals.
+#[derive(Debug, Copy, Clone)]
+pub struct HumanReadableUnit(u64, Format);
 
 impl fmt::Display for HumanReadableUnit {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let num = self.0 as f32;
+        let format = self.1;
 
-        let (num, unit) = match num.log10() as u64 {
-            0..=2 => (num, 'B'),
-            3..=5 => (num / 1_000.0, 'K'),
-            6..=8 => (num / 1_000_000.0, 'M'),
-            _ => (num / 1_000_000_000.0, 'G'),
+        let (mut num, unit) = match format.units {
+            Units::Bits => (self.0 as f64 * 8.0, "bit"),
+            Units::Bytes => (self.0 as f64, "B"),
+        };
+        let (base, prefixes) = if format.iec {
+            (1024.0, ["Ki", "Mi", "Gi", "Ti", "Pi"])
+        } else {
+            (1000.0, ["K", "M", "G", "T", "P"])
         };
 
-        write!(f, "{num}{unit}")
+        let mut prefix = None;
+        for p in prefixes {
+            if num < base {
+                break;
+            }
+            num /= base;
+            prefix = Some(p);
+        }
+
+        match prefix {
+            Some(prefix) => write!(f, "{num:.2} {prefix}{unit}"),
+            None => write!(f, "{num} {unit}"),
+        }
     }
 }
 
 impl HumanReadableUnit {
-    pub fn new(bytes: u64) -> Self {
-        Self(bytes)
+    pub fn new(bytes: u64, format: Format) -> Self {
+        Self(bytes, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(bytes: u64, units: Units, iec: bool) -> String {
+        HumanReadableUnit::new(bytes, Format { units, iec }).to_string()
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(display(999, Units::Bytes, false), "999 B");
+        assert_eq!(display(1500, Units::Bytes, false), "1.50 KB");
+        assert_eq!(display(1536, Units::Bytes, true), "1.50 KiB");
+        assert_eq!(display(125_000, Units::Bits, false), "1.00 Mbit");
+        assert_eq!(display(3 << 30, Units::Bytes, true), "3.00 GiB");
     }
 }
@@ -0,0 +1,91 @@
+use super::{println_on_level, Destination, Interval, Level};
+use crate::ping::{logined, PingArgs, Stats};
+
+use clap_verbosity_flag::Verbosity;
+use openssh::{Error, Session};
+use std::future::Future;
+use std::num::NonZeroU64;
+use std::time::Duration;
+
+/// Number of echo probes used to measure the idle latency.
+const IDLE_PROBES: u64 = 10;
+
+fn ping_args(count: u64) -> PingArgs {
+    PingArgs::new(
+        Interval(Duration::from_millis(200)),
+        count,
+        NonZeroU64::new(56).unwrap(),
+    )
+}
+
+/// Per-probe output of ping would be interleaved with speed test.
+fn quiet() -> Verbosity {
+    Verbosity::new(0, 1)
+}
+
+/// Measure RTT by echoing through cat on remote before the speed test.
+pub async fn idle(session: &Session) -> Result<Vec<Duration>, Error> {
+    let mut rtts = Vec::new();
+    logined::main_loop(ping_args(IDLE_PROBES), quiet(), session, &mut rtts).await?;
+    Ok(rtts)
+}
+
+/// Measure RTT by echoing through cat on remote until `load` completes.
+pub async fn under_load<F: Future>(
+    session: &Session,
+    verbose: &Verbosity,
+    load: F,
+) -> (F::Output, Vec<Duration>) {
+    let mut rtts = Vec::new();
+
+    tokio::pin!(load);
+
+    let output = tokio::select! {
+        output = &mut load => output,
+        res = logined::main_loop(ping_args(u64::MAX), quiet(), session, &mut rtts) => {
+            if let Err(err) = res {
+                println_on_level!(
+                    *verbose,
+                    Level::Warn,
+                    "Failed to measure latency under load: {err}"
+                );
+            }
+            load.await
+        }
+    };
+
+    (output, rtts)
+}
+
+/// Grade the increase of average RTT under load like the bufferbloat tests.
+fn grade(increase: Duration) -> &'static str {
+    match increase.as_millis() {
+        0..=4 => "A+",
+        5..=29 => "A",
+        30..=59 => "B",
+        60..=199 => "C",
+        200..=399 => "D",
+        _ => "F",
+    }
+}
+
+pub fn print_latency(dest: &Destination, idle: &[Duration], loaded: &[Duration]) {
+    println!("--- {dest} latency under load ---");
+
+    let (idle, loaded) = match (Stats::new(idle), Stats::new(loaded)) {
+        (Some(idle), Some(loaded)) => (idle, loaded),
+        _ => {
+            println!("Not enough probes to measure latency under load");
+            return;
+        }
+    };
+
+    println!("idle:   {idle}");
+    println!("loaded: {loaded}");
+
+    let increase = loaded.avg().saturating_sub(idle.avg());
+    println!(
+        "latency increase = {increase:#?}, grade = {}",
+        grade(increase)
+    );
+}
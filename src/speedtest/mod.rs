@@ -5,15 +5,62 @@ mod download;
 use download::speedtest_download;
 
 mod human_readable_unit;
+use human_readable_unit::{Format, Units};
 
-use super::utility::{println_on_level, Level};
-use super::SshSessionBuilder;
+mod byte_count;
+use byte_count::{ByteCount, ByteCountRange};
+
+mod transfer;
+use transfer::{Direction, Transfer};
+
+mod payload;
+use payload::Payload;
+
+mod comparison;
+use comparison::{run_variants, Variant};
+
+mod remote_source;
+use remote_source::RemoteSource;
+
+mod checksum;
+use checksum::Checksum;
+
+mod transport;
+use transport::Transport;
+
+mod sftp;
+use sftp::speedtest_sftp;
+
+mod forward;
+use forward::speedtest_forward;
+
+mod cpu_time;
+use cpu_time::{CpuUsage, LocalCpu};
+
+mod disk;
+use disk::disk_test;
+
+mod ramp;
+
+mod pacing;
+use pacing::Bitrate;
+
+mod latency;
+use latency::print_latency;
+
+use super::utility::{println_if_not_quiet, println_on_level, Level};
+use super::{Destination, Interval, SshSessionBuilder};
 
 use clap::Parser;
 use clap_verbosity_flag::Verbosity;
-use openssh::Error;
+use futures_util::future::try_join_all;
+use openssh::{Error, Session};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::time::Instant;
 
-#[derive(Debug, Parser, Copy, Clone)]
+#[derive(Debug, Parser, Clone)]
 pub struct SpeedTestArgs {
     /// Disable testing upload speed.
     #[clap(long)]
@@ -22,6 +69,503 @@ pub struct SpeedTestArgs {
     /// Disable testing download speed.
     #[clap(long)]
     no_download: bool,
+
+    /// Time to transfer for in seconds (can be float).
+    ///
+    /// Defaults to 10s unless --bytes is specified.
+    #[clap(short, long, env = "SSH_UTILS_SPEED_TEST_DURATION")]
+    duration: Option<Interval>,
+
+    /// Number of bytes to transfer (e.g. 400K, 1G).
+    ///
+    /// If --duration is also specified, stop on whichever is reached first.
+    #[clap(short, long)]
+    bytes: Option<ByteCount>,
+
+    /// Omit the first n seconds (can be float) from the reported speed
+    /// to skip the warm-up.
+    #[clap(short, long, default_value_t = Interval::from_secs(0))]
+    omit: Interval,
+
+    /// Interval of reporting speed in seconds (can be float), 0 to disable.
+    #[clap(short, long, default_value_t = Interval::from_secs(1))]
+    report_interval: Interval,
+
+    /// Number of parallel streams to run.
+    #[clap(short = 'P', long, default_value_t = NonZeroUsize::new(1).unwrap())]
+    parallel: NonZeroUsize,
+
+    /// Run each parallel stream over its own ssh multiplex master
+    /// instead of sharing one.
+    #[clap(long)]
+    sessions: bool,
+
+    /// Test upload and download simultaneously.
+    #[clap(long, conflicts_with_all = &["no-upload", "no-download"])]
+    bidir: bool,
+
+    /// Data to transfer: zero, pattern, random or file:<path>.
+    ///
+    /// pattern is "y\n" repeated, the output of yes. For download, the
+    /// path of file:<path> is on the remote.
+    #[clap(long, default_value_t = Payload::Pattern)]
+    payload: Payload,
+
+    /// Channel to transfer data over: exec, sftp or forward.
+    ///
+    /// exec pipes data through dd/head on remote, sftp writes and
+    /// reads a temporary file on remote via the sftp subsystem, while
+    /// forward sends data through a port forward to a relay on remote,
+    /// which requires python3 on remote.
+    ///
+    /// forward uses a local forward in both directions (not a remote
+    /// forward for download), and cannot be used with --control-path
+    /// since port forwards cannot be closed on the ssh multiplex master.
+    #[clap(long, default_value_t = Transport::Exec)]
+    transport: Transport,
+
+    /// Program on remote writing the payload for download: auto, head, dd or cat.
+    ///
+    /// auto picks the first one available on remote.
+    #[clap(long, default_value_t = RemoteSource::Auto)]
+    remote_source: RemoteSource,
+
+    /// Verify the data transferred by comparing its sha256sum/md5sum
+    /// with the one computed on remote.
+    ///
+    /// Requires --bytes so that the download ends on remote, unless only
+    /// upload is tested with --no-download.
+    #[clap(long)]
+    verify: bool,
+
+    /// Size of the buffer used for each read/write (e.g. 4K, 64K), at
+    /// most 16M, where K and M are 1024 and 1024².
+    #[clap(long, default_value_t = ByteCount(4096), parse(try_from_str = parse_buffer_size))]
+    buffer_size: ByteCount,
+
+    /// Rerun the test with buffer sizes doubling from start to end
+    /// (e.g. 4K..1M), at most 16M, and compare the results.
+    ///
+    /// Like --buffer-size, K and M are 1024 and 1024².
+    #[clap(
+        long,
+        conflicts_with_all = &["compare-compression", "ciphers", "macs"],
+        parse(try_from_str = parse_buffer_size_range)
+    )]
+    sweep_buffer: Option<ByteCountRange>,
+
+    /// Run the test with and without ssh compression and compare the results.
+    #[clap(long, conflicts_with_all = &["ciphers", "macs"])]
+    compare_compression: bool,
+
+    /// Transfer the same number of bytes (--bytes, 256M by default) over
+    /// every transport and compare their speed and CPU time.
+    #[clap(
+        long,
+        conflicts_with_all = &["transport", "sweep-buffer", "compare-compression", "ciphers", "macs"]
+    )]
+    compare: bool,
+
+    /// Report time to first byte, time to reach 90% of the peak rate and
+    /// the rate over time of each stream, averaged every 100ms, as data
+    /// and as a chart.
+    #[clap(long)]
+    ramp: bool,
+
+    /// Unit of sizes and rates printed: bits or bytes.
+    #[clap(long, env = "SSH_UTILS_UNITS", default_value_t = Units::Bytes)]
+    units: Units,
+
+    /// Print sizes and rates with IEC prefixes (Ki = 1024, e.g. MiB/s)
+    /// instead of SI prefixes (K = 1000, e.g. MB/s).
+    #[clap(long)]
+    iec: bool,
+
+    /// Pace upload of each stream at this bitrate (e.g. 800K, 50M) with
+    /// a token bucket, and report stalls and the latency under load.
+    #[clap(long)]
+    bitrate: Option<Bitrate>,
+
+    /// Report CPU usage of this process and of the ssh multiplex masters,
+    /// which do the encryption, sampled while the test runs, and of
+    /// remote read from /proc/stat.
+    ///
+    /// Local CPU usage is read from /proc and only available on Linux.
+    #[clap(long)]
+    cpu: bool,
+
+    /// Measure RTT by echoing through cat on remote while idle and while
+    /// the speed test saturates the link, then grade the bufferbloat.
+    #[clap(long)]
+    latency: bool,
+
+    /// Also measure sequential write and read speed of the remote
+    /// filesystem at this directory, after the network tests.
+    ///
+    /// Combine with --no-upload --no-download to test only the disk.
+    #[clap(
+        long,
+        conflicts_with_all = &["compare", "sweep-buffer", "compare-compression", "ciphers", "macs"]
+    )]
+    disk: Option<PathBuf>,
+
+    /// Number of bytes to write and read in the disk test (e.g. 1G).
+    #[clap(long, default_value_t = ByteCount(256 * 1024 * 1024))]
+    disk_bytes: ByteCount,
+
+    /// Drop the page cache of the whole remote machine (requires root)
+    /// before reading in the disk test, so that the read is cold even if
+    /// dd does not support O_DIRECT.
+    #[clap(long)]
+    drop_caches: bool,
+
+    /// Comma-separated ciphers to benchmark, each over a new ssh multiplex master.
+    #[clap(long, use_value_delimiter = true)]
+    ciphers: Vec<String>,
+
+    /// Comma-separated MACs to benchmark, each over a new ssh multiplex master.
+    ///
+    /// If --ciphers is also specified, every combination is benchmarked.
+    #[clap(long, use_value_delimiter = true)]
+    macs: Vec<String>,
+}
+
+/// Maximum of --buffer-size, which is allocated at once.
+const MAX_BUFFER_SIZE: u64 = 16 * 1024 * 1024;
+
+fn parse_buffer_size(s: &str) -> Result<ByteCount, String> {
+    let buffer_size = ByteCount::parse_binary(s)?;
+    if buffer_size.0 == 0 || buffer_size.0 > MAX_BUFFER_SIZE {
+        Err(format!(
+            "Expected 1 to {MAX_BUFFER_SIZE} bytes, found {buffer_size}"
+        ))
+    } else {
+        Ok(buffer_size)
+    }
+}
+
+fn parse_buffer_size_range(s: &str) -> Result<ByteCountRange, String> {
+    let range = ByteCountRange::parse_binary(s)?;
+    parse_buffer_size(&range.start.to_string())?;
+    parse_buffer_size(&range.end.to_string())?;
+    Ok(range)
+}
+
+impl SpeedTestArgs {
+    /// Return the instant at which the transfer should stop.
+    fn deadline(&self, start: Instant) -> Option<Instant> {
+        match (self.duration, self.bytes) {
+            (Some(duration), _) => Some(start + duration.0),
+            (None, Some(_)) => None,
+            (None, None) => Some(start + Duration::from_secs(10)),
+        }
+    }
+
+    /// Check that the test does not need to change ssh options, which
+    /// cannot be changed on a resumed ssh multiplex master.
+    /// Check the args requiring others, which is not left to clap since
+    /// the required args can also be given in config file or inventory.
+    pub fn check_requires(&self) -> Result<(), String> {
+        if self.verify && !self.no_download && self.bytes.is_none() {
+            Err("--verify requires --bytes unless --no-download".to_string())
+        } else if self.drop_caches && self.disk.is_none() {
+            Err("--drop-caches requires --disk".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check_resumable(&self) -> Result<(), String> {
+        if self.compare_compression {
+            Err("--compare-compression cannot be used with --control-path, \
+                 as the compression of the resumed master cannot be changed"
+                .to_string())
+        } else if !self.ciphers.is_empty() || !self.macs.is_empty() {
+            Err("--ciphers and --macs cannot be used with --control-path, \
+                 as the algorithms of the resumed master cannot be changed"
+                .to_string())
+        } else if self.transport == Transport::Forward || self.compare {
+            Err(
+                "--transport forward and --compare cannot be used with --control-path, \
+                 as port forwards cannot be closed on the resumed master"
+                    .to_string(),
+            )
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Return the format of sizes and rates printed.
+    fn format(&self) -> Format {
+        Format {
+            units: self.units,
+            iec: self.iec,
+        }
+    }
+
+    fn buffer_size(&self) -> usize {
+        self.buffer_size.0 as usize
+    }
+
+    /// Return the maximum number of bytes to transfer.
+    fn byte_limit(&self) -> u64 {
+        self.bytes.map(|bytes| bytes.0).unwrap_or(u64::MAX)
+    }
+}
+
+/// Programs used on remote, probed once per run.
+#[derive(Debug, Copy, Clone)]
+struct Tools {
+    source: RemoteSource,
+    checksum: Option<Checksum>,
+}
+
+/// Run `args.parallel` streams in `direction` spread over `sessions`,
+/// then print the result of each stream and their sum.
+///
+/// Return the sum of all streams.
+async fn run_streams(
+    args: &SpeedTestArgs,
+    verbose: &Verbosity,
+    sessions: &[Session],
+    direction: Direction,
+    tools: Tools,
+) -> Result<Transfer, Error> {
+    let parallel = args.parallel.get();
+    let prefix = if args.bidir {
+        format!("[{direction:<8}]")
+    } else {
+        String::new()
+    };
+    let labels: Vec<String> = (1..=parallel)
+        .map(|stream| {
+            if parallel > 1 {
+                format!("{prefix}[{stream:>3}] ")
+            } else if args.bidir {
+                format!("{prefix} ")
+            } else {
+                String::new()
+            }
+        })
+        .collect();
+
+    let transfers = try_join_all(labels.iter().enumerate().map(|(i, label)| {
+        let session = &sessions[i % sessions.len()];
+        let verbose = verbose.clone();
+
+        async move {
+            match (args.transport, direction) {
+                (Transport::Sftp, direction) => {
+                    speedtest_sftp(args, verbose, session, label, direction, tools).await
+                }
+                (Transport::Forward, direction) => {
+                    speedtest_forward(args, verbose, session, label, direction, tools).await
+                }
+                (Transport::Exec, Direction::Upload) => {
+                    speedtest_upload(args, verbose, session, label, tools).await
+                }
+                (Transport::Exec, Direction::Download) => {
+                    speedtest_download(args, verbose, session, label, tools).await
+                }
+            }
+        }
+    }))
+    .await?;
+
+    for (transfer, label) in transfers.iter().zip(&labels) {
+        transfer.print(label, direction, args.format());
+    }
+
+    let sum = Transfer::sum(&transfers);
+    if parallel > 1 {
+        sum.print(&format!("{prefix}[SUM] "), direction, args.format());
+    }
+
+    Ok(sum)
+}
+
+/// Run the test of each direction enabled.
+async fn run_directions(
+    args: &SpeedTestArgs,
+    verbose: &Verbosity,
+    sessions: &[Session],
+    tools: Tools,
+) -> Result<Vec<(Direction, Transfer)>, Error> {
+    let mut results = Vec::new();
+
+    if args.bidir {
+        let (upload, download) = tokio::try_join!(
+            run_streams(args, verbose, sessions, Direction::Upload, tools),
+            run_streams(args, verbose, sessions, Direction::Download, tools),
+        )?;
+
+        results.push((Direction::Upload, upload));
+        results.push((Direction::Download, download));
+    } else {
+        if !args.no_upload {
+            let upload = run_streams(args, verbose, sessions, Direction::Upload, tools).await?;
+            results.push((Direction::Upload, upload));
+        }
+
+        if !args.no_download {
+            let download = run_streams(args, verbose, sessions, Direction::Download, tools).await?;
+            results.push((Direction::Download, download));
+        }
+    }
+
+    Ok(results)
+}
+
+/// Return the result of each direction tested, and the local CPU usage
+/// if measured for --cpu or --compare.
+async fn run_once(
+    args: &SpeedTestArgs,
+    verbose: &Verbosity,
+    builder: &SshSessionBuilder<'_>,
+) -> Result<(Vec<(Direction, Transfer)>, Option<LocalCpu>), Error> {
+    let dest = builder.dest();
+    let n = if args.sessions {
+        args.parallel.get()
+    } else {
+        1
+    };
+
+    let mut sessions = Vec::with_capacity(n);
+    let mut res = Ok((Vec::new(), None));
+    for _ in 0..n {
+        println_on_level!(*verbose, Level::Debug, "Attempting to connect to {dest}");
+        match builder.connect().await {
+            Ok(session) => sessions.push(session),
+            Err(err) => {
+                res = Err(err);
+                break;
+            }
+        }
+    }
+
+    if res.is_ok() {
+        res = async {
+            let source = if args.no_download || args.transport == Transport::Sftp {
+                args.remote_source
+            } else {
+                let source = args.remote_source.resolve(&sessions[0], verbose).await?;
+                println_if_not_quiet!(
+                    *verbose,
+                    "Download source: {}",
+                    source.command(&args.payload, args.bytes.map(|bytes| bytes.0))
+                );
+                source
+            };
+            let checksum = if args.verify {
+                Some(Checksum::probe(&sessions[0], verbose).await?)
+            } else {
+                None
+            };
+            if args.transport == Transport::Forward {
+                forward::probe(&sessions[0], verbose).await?;
+            }
+            let tools = Tools { source, checksum };
+
+            let idle = if args.latency || args.bitrate.is_some() {
+                Some(latency::idle(&sessions[0]).await?)
+            } else {
+                None
+            };
+            let cpu_usage = if args.cpu || args.compare {
+                Some(CpuUsage::start(&sessions, args.cpu).await)
+            } else {
+                None
+            };
+
+            let results = run_directions(args, verbose, &sessions, tools);
+            let results = match idle {
+                Some(idle) => {
+                    let (results, loaded) =
+                        latency::under_load(&sessions[0], verbose, results).await;
+                    print_latency(dest, &idle, &loaded);
+                    results
+                }
+                None => results.await,
+            };
+
+            let local_cpu = match cpu_usage {
+                Some(cpu_usage) => {
+                    cpu_usage
+                        .finish(&sessions[0], dest, verbose, args.cpu)
+                        .await
+                }
+                None => None,
+            };
+            let results = results?;
+
+            if let Some(dir) = &args.disk {
+                disk_test(
+                    &sessions[0],
+                    dir,
+                    args.disk_bytes.0,
+                    args.drop_caches,
+                    verbose,
+                )
+                .await?
+                .print(dir, args.format());
+            }
+
+            Ok((results, local_cpu))
+        }
+        .await;
+    }
+
+    for session in sessions {
+        let close_res = builder.close(session).await;
+        res = res.and_then(|results| close_res.map(|_| results));
+    }
+
+    res
+}
+
+/// Return a variant for every combination of `args.ciphers` and `args.macs`.
+fn algorithm_variants<'a>(
+    args: &SpeedTestArgs,
+    builder: &SshSessionBuilder<'a>,
+) -> Result<Vec<Variant<'a>>, Error> {
+    fn or_default(algorithms: &[String]) -> Vec<Option<&str>> {
+        if algorithms.is_empty() {
+            vec![None]
+        } else {
+            algorithms
+                .iter()
+                .map(|algorithm| Some(&**algorithm))
+                .collect()
+        }
+    }
+
+    let mut variants = Vec::new();
+    for cipher in or_default(&args.ciphers) {
+        for mac in or_default(&args.macs) {
+            let options: Vec<_> = [("Ciphers", cipher), ("MACs", mac)]
+                .into_iter()
+                .filter_map(|(key, value)| Some((key, value?)))
+                .collect();
+
+            let name = options
+                .iter()
+                .map(|(_, value)| *value)
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            let mut builder = builder.clone();
+            let config = builder.options(&options).map_err(Error::Connect)?;
+
+            variants.push(Variant {
+                name,
+                args: args.clone(),
+                builder,
+                _config: Some(config),
+            });
+        }
+    }
+
+    Ok(variants)
 }
 
 pub async fn run(
@@ -29,18 +573,51 @@ pub async fn run(
     verbose: Verbosity,
     builder: SshSessionBuilder<'_>,
 ) -> Result<(), Error> {
-    let dest = builder.dest();
+    let variants = if args.compare {
+        let mut args = args.clone();
+        if args.bytes.is_none() {
+            args.bytes = Some(ByteCount(256 * 1024 * 1024));
+        }
 
-    println_on_level!(verbose, Level::Debug, "Attempting to connect to {dest}");
-    let session = builder.connect().await?;
+        [Transport::Exec, Transport::Sftp, Transport::Forward]
+            .into_iter()
+            .map(|transport| {
+                let mut args = args.clone();
+                args.transport = transport;
 
-    if !args.no_upload {
-        speedtest_upload(verbose.clone(), &session).await?;
-    }
+                Variant::new(transport.to_string(), args, builder.clone())
+            })
+            .collect()
+    } else if args.compare_compression {
+        [false, true]
+            .into_iter()
+            .map(|compression| {
+                let mut builder = builder.clone();
+                builder.compression(compression);
 
-    if !args.no_download {
-        speedtest_download(verbose, &session).await?;
-    }
+                let name = if compression {
+                    "compression on"
+                } else {
+                    "compression off"
+                };
+                Variant::new(name.to_string(), args.clone(), builder)
+            })
+            .collect()
+    } else if let Some(sweep_buffer) = args.sweep_buffer {
+        sweep_buffer
+            .doubling()
+            .map(|buffer_size| {
+                let mut args = args.clone();
+                args.buffer_size = buffer_size;
+
+                Variant::new(format!("buffer size {buffer_size}"), args, builder.clone())
+            })
+            .collect()
+    } else if !args.ciphers.is_empty() || !args.macs.is_empty() {
+        algorithm_variants(&args, &builder)?
+    } else {
+        return run_once(&args, &verbose, &builder).await.map(|_| ());
+    };
 
-    session.close().await
+    run_variants(&verbose, builder.dest(), variants).await
 }
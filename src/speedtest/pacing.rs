@@ -0,0 +1,262 @@
+use super::human_readable_unit::{Format, HumanReadableUnit};
+use super::println_if_not_quiet;
+use super::transfer::rate;
+
+use clap_verbosity_flag::Verbosity;
+use std::fmt;
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+
+/// Writes blocking for longer than this are counted as stalls.
+const STALL_THRESHOLD: Duration = Duration::from_millis(100);
+
+/// Maximum time worth of tokens that can be accumulated.
+const BURST: Duration = Duration::from_millis(10);
+
+/// Bits per second, parsed from strings like "800K", "50M" or "1G".
+///
+/// Like iperf, K, M and G are powers of 1000.
+#[derive(Debug, Copy, Clone)]
+pub struct Bitrate(pub u64);
+
+impl FromStr for Bitrate {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (s, exp) = match s.char_indices().last() {
+            Some((i, 'K' | 'k')) => (&s[..i], 1),
+            Some((i, 'M' | 'm')) => (&s[..i], 2),
+            Some((i, 'G' | 'g')) => (&s[..i], 3),
+            _ => (s, 0),
+        };
+
+        let num: u64 = s.parse()?;
+        Ok(Self(num.saturating_mul(1000_u64.pow(exp))))
+    }
+}
+
+impl fmt::Display for Bitrate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (num, unit) = match self.0 {
+            n if n >= 1_000_000_000 && n % 1_000_000_000 == 0 => (n / 1_000_000_000, "G"),
+            n if n >= 1_000_000 && n % 1_000_000 == 0 => (n / 1_000_000, "M"),
+            n if n >= 1_000 && n % 1_000 == 0 => (n / 1_000, "K"),
+            n => (n, ""),
+        };
+
+        write!(f, "{num}{unit}bit/s")
+    }
+}
+
+/// Token bucket pacing writes at a fixed bitrate, which also counts
+/// the writes that stall.
+///
+/// The bytes allowed and the start of the write are kept here, outside of
+/// the write future, so that they survive its cancellation.
+#[derive(Debug)]
+pub struct Pacer {
+    bitrate: Bitrate,
+    /// Bytes per second.
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+    /// Bytes allowed by `acquire` that are not written yet.
+    granted: usize,
+    /// Start of the write not completed yet.
+    write_start: Option<Instant>,
+    stalls: u64,
+    stalled: Duration,
+}
+
+impl Pacer {
+    pub fn new(bitrate: Bitrate) -> Self {
+        Self {
+            bitrate,
+            rate: bitrate.0 as f64 / 8.0,
+            tokens: 0.0,
+            last: Instant::now(),
+            granted: 0,
+            write_start: None,
+            stalls: 0,
+            stalled: Duration::ZERO,
+        }
+    }
+
+    fn burst(&self) -> f64 {
+        (self.rate * BURST.as_secs_f64()).max(1.0)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        self.tokens = (self.tokens + (now - self.last).as_secs_f64() * self.rate).min(self.burst());
+        self.last = now;
+    }
+
+    /// Wait until `len` bytes can be written, and return the number of
+    /// bytes allowed, which might be less than `len`.
+    ///
+    /// Bytes allowed are kept until written by [`Pacer::written`], so a
+    /// cancelled write does not lose them. Cancel safe.
+    pub async fn acquire(&mut self, len: usize) -> usize {
+        if self.granted == 0 {
+            let len = len.min(self.burst() as usize).max(1);
+
+            self.refill();
+            if self.tokens < len as f64 {
+                sleep(Duration::from_secs_f64(
+                    (len as f64 - self.tokens) / self.rate,
+                ))
+                .await;
+                self.refill();
+            }
+
+            self.tokens -= len as f64;
+            self.granted = len;
+        }
+
+        self.granted.min(len).max(1)
+    }
+
+    /// Mark the start of a write, unless a write cancelled before is
+    /// retried, so that a stall is timed from its start.
+    pub fn start_write(&mut self) {
+        self.write_start.get_or_insert_with(Instant::now);
+    }
+
+    /// Record that `cnt` bytes are written, and count the write as a stall
+    /// if it took too long.
+    pub fn written(&mut self, cnt: usize) {
+        self.granted = self.granted.saturating_sub(cnt);
+
+        if let Some(start) = self.write_start.take() {
+            let elapsed = start.elapsed();
+            if elapsed > STALL_THRESHOLD {
+                self.stalls += 1;
+                self.stalled += elapsed;
+            }
+        }
+    }
+
+    pub fn print(
+        &self,
+        verbose: &Verbosity,
+        label: &str,
+        n: u64,
+        elapsed: Duration,
+        format: Format,
+    ) {
+        println_if_not_quiet!(
+            *verbose,
+            "{label}paced at {}: achieved {}/s, {} stalls (> {STALL_THRESHOLD:#?}) for {:#?} in total, {} sent",
+            self.bitrate,
+            rate(n, elapsed, format),
+            self.stalls,
+            self.stalled,
+            HumanReadableUnit::new(n, format)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<u64> {
+        s.parse::<Bitrate>().ok().map(|bitrate| bitrate.0)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("800"), Some(800));
+        assert_eq!(parse("800K"), Some(800_000));
+        assert_eq!(parse("50m"), Some(50_000_000));
+        assert_eq!(parse("1G"), Some(1_000_000_000));
+
+        assert_eq!(parse("1.5M"), None);
+        assert_eq!(parse("1T"), None);
+    }
+
+    #[test]
+    fn test_display() {
+        for s in ["800bit/s", "800Kbit/s", "1500Kbit/s", "50Mbit/s", "1Gbit/s"] {
+            let bitrate: Bitrate = s.strip_suffix("bit/s").unwrap().parse().unwrap();
+            assert_eq!(bitrate.to_string(), s);
+        }
+    }
+
+    /// Write `len` bytes with `pacer`, `chunk` at a time.
+    async fn write(pacer: &mut Pacer, len: usize, chunk: usize) {
+        let mut n = 0;
+        while n < len {
+            let cnt = pacer.acquire(chunk).await;
+            pacer.start_write();
+            pacer.written(cnt);
+            n += cnt;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pacer_rate() {
+        // 1MB/s, with bursts of 10KB.
+        let mut pacer = Pacer::new(Bitrate(8_000_000));
+        let start = Instant::now();
+
+        write(&mut pacer, 1_000_000, 65536).await;
+
+        let elapsed = start.elapsed().as_secs_f64();
+        assert!((0.99..=1.01).contains(&elapsed), "{elapsed}");
+        assert_eq!(pacer.stalls, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pacer_idle() {
+        let mut pacer = Pacer::new(Bitrate(8_000_000));
+
+        // Tokens accumulate up to a burst only while idle.
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let start = Instant::now();
+        write(&mut pacer, 100_000, 65536).await;
+
+        let elapsed = start.elapsed().as_secs_f64();
+        assert!((0.089..0.091).contains(&elapsed), "{elapsed}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pacer_partial_write() {
+        let mut pacer = Pacer::new(Bitrate(8_000_000));
+
+        assert_eq!(pacer.acquire(4096).await, 4096);
+        pacer.written(1000);
+
+        // The rest allowed is returned again without waiting for tokens.
+        let start = Instant::now();
+        assert_eq!(pacer.acquire(100).await, 100);
+        assert_eq!(pacer.acquire(4096).await, 3096);
+        pacer.written(3096);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        assert_eq!(pacer.acquire(4096).await, 4096);
+        assert!(start.elapsed() > Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pacer_stalls() {
+        let mut pacer = Pacer::new(Bitrate(8_000_000));
+
+        pacer.start_write();
+        tokio::time::advance(Duration::from_millis(50)).await;
+        pacer.written(1);
+        assert_eq!(pacer.stalls, 0);
+
+        // A write cancelled and retried is timed from its first start.
+        pacer.start_write();
+        tokio::time::advance(Duration::from_millis(80)).await;
+        pacer.start_write();
+        tokio::time::advance(Duration::from_millis(80)).await;
+        pacer.written(1);
+        assert_eq!(pacer.stalls, 1);
+        assert_eq!(pacer.stalled, Duration::from_millis(160));
+    }
+}
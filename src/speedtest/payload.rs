@@ -0,0 +1,184 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// Data of [`Payload::Pattern`], the output of `yes` which generates it on
+/// remote for download, so that both directions transfer the same data.
+const PATTERN: &[u8] = b"y\n";
+
+/// Content of the data transferred.
+#[derive(Debug, Clone)]
+pub enum Payload {
+    Zero,
+    Pattern,
+    Random,
+    /// Local file to upload, or remote file to download.
+    File(PathBuf),
+}
+
+impl FromStr for Payload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(Payload::Zero),
+            "pattern" => Ok(Payload::Pattern),
+            "random" => Ok(Payload::Random),
+            s => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(Payload::File(path.into())),
+                _ => Err(format!(
+                    "Expected zero, pattern, random or file:<path>, found {s}"
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Payload::Zero => f.write_str("zero"),
+            Payload::Pattern => f.write_str("pattern"),
+            Payload::Random => f.write_str("random"),
+            Payload::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+impl Payload {
+    /// Create a local source of the payload with chunks of `len` bytes.
+    pub async fn local_source(&self, len: usize) -> io::Result<PayloadSource> {
+        Ok(match self {
+            Payload::Zero => PayloadSource::Static(vec![0; len]),
+            Payload::Pattern => PayloadSource::Pattern {
+                // Long enough for a chunk at any offset in the pattern.
+                buffer: PATTERN
+                    .iter()
+                    .copied()
+                    .cycle()
+                    .take(len + PATTERN.len() - 1)
+                    .collect(),
+                len,
+                offset: 0,
+            },
+            Payload::Random => PayloadSource::Random(vec![0; len]),
+            Payload::File(path) => PayloadSource::File {
+                file: File::open(path).await?,
+                buffer: vec![0; len],
+            },
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum PayloadSource {
+    Static(Vec<u8>),
+    /// Chunks of `len` bytes of [`PATTERN`] repeated, the next one starting
+    /// at `offset` in it.
+    Pattern {
+        buffer: Vec<u8>,
+        len: usize,
+        offset: usize,
+    },
+    Random(Vec<u8>),
+    File {
+        file: File,
+        buffer: Vec<u8>,
+    },
+}
+
+impl PayloadSource {
+    /// Return the next chunk of the payload.
+    ///
+    /// The file is read from the beginning again on EOF.
+    pub async fn next(&mut self) -> io::Result<&[u8]> {
+        match self {
+            PayloadSource::Static(buffer) => Ok(buffer),
+            PayloadSource::Pattern {
+                buffer,
+                len,
+                offset,
+            } => {
+                let start = *offset;
+                *offset = (start + *len) % PATTERN.len();
+                Ok(&buffer[start..start + *len])
+            }
+            PayloadSource::Random(buffer) => {
+                fastrand::fill(buffer);
+                Ok(buffer)
+            }
+            PayloadSource::File { file, buffer } => {
+                let mut cnt = file.read(buffer).await?;
+                if cnt == 0 {
+                    file.rewind().await?;
+                    cnt = file.read(buffer).await?;
+                }
+
+                if cnt == 0 {
+                    Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Payload file is empty",
+                    ))
+                } else {
+                    Ok(&buffer[..cnt])
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        for s in ["zero", "pattern", "random", "file:/tmp/a b"] {
+            assert_eq!(s.parse::<Payload>().unwrap().to_string(), s);
+        }
+
+        match "file:data.bin".parse() {
+            Ok(Payload::File(path)) => assert_eq!(path, PathBuf::from("data.bin")),
+            res => panic!("Unexpected {res:?}"),
+        }
+
+        assert!("file:".parse::<Payload>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"abcde").unwrap();
+
+        // The file is read again from the beginning on EOF.
+        let payload = Payload::File(file.path().into());
+        let mut source = payload.local_source(3).await.unwrap();
+        for chunk in [&b"abc"[..], b"de", b"abc"] {
+            assert_eq!(source.next().await.unwrap(), chunk);
+        }
+
+        std::fs::write(file.path(), b"").unwrap();
+        let mut source = payload.local_source(3).await.unwrap();
+        let err = source.next().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn test_pattern() {
+        for len in [1, 2, 3, 4095] {
+            let mut source = Payload::Pattern.local_source(len).await.unwrap();
+            let mut data = Vec::new();
+            for _ in 0..3 {
+                let chunk = source.next().await.unwrap();
+                assert_eq!(chunk.len(), len);
+                data.extend_from_slice(chunk);
+            }
+
+            let expected: Vec<u8> = b"y\n".iter().copied().cycle().take(3 * len).collect();
+            assert_eq!(data, expected, "{len}");
+        }
+    }
+}
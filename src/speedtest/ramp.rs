@@ -0,0 +1,172 @@
+use super::human_readable_unit::{Format, HumanReadableUnit};
+use super::println_if_not_quiet;
+use super::transfer::Ticker;
+
+use clap_verbosity_flag::Verbosity;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Interval of sampling the number of bytes transferred.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Window the rate is averaged over, smoothing out bursts of the channel.
+const WINDOW: Duration = Duration::from_millis(100);
+
+/// Maximum number of rows in the chart.
+const CHART_ROWS: usize = 20;
+
+/// Width of the bar of the peak rate in the chart.
+const CHART_WIDTH: usize = 40;
+
+/// Number of bytes transferred sampled at fine granularity.
+#[derive(Debug)]
+pub struct Ramp {
+    ticker: Ticker,
+    start: Instant,
+    first_byte: Option<Duration>,
+    samples: Vec<(Duration, u64)>,
+}
+
+impl Ramp {
+    pub fn new(start: Instant, enabled: bool) -> Self {
+        Self {
+            ticker: Ticker::new(start, enabled.then_some(SAMPLE_INTERVAL)),
+            start,
+            first_byte: None,
+            samples: vec![(Duration::ZERO, 0)],
+        }
+    }
+
+    /// Cancel safe, never completes if sampling is disabled.
+    pub async fn tick(&mut self) {
+        self.ticker.tick().await
+    }
+
+    pub fn sample(&mut self, n: u64) {
+        let elapsed = self.start.elapsed();
+        if n > 0 && self.first_byte.is_none() {
+            self.first_byte = Some(elapsed);
+        }
+        self.samples.push((elapsed, n));
+    }
+
+    /// Return the end of each window and the rate in it.
+    fn curve(&self) -> Vec<(Duration, f64)> {
+        let mut curve = Vec::new();
+        let mut last = self.samples[0];
+
+        for &(elapsed, n) in &self.samples[1..] {
+            if elapsed - last.0 >= WINDOW {
+                let secs = (elapsed - last.0).as_secs_f64();
+                curve.push((elapsed, (n - last.1) as f64 / secs));
+                last = (elapsed, n);
+            }
+        }
+
+        curve
+    }
+
+    /// Print time to first byte, time to reach 90% of the peak rate, and
+    /// the rate-over-time curve as data and as a chart.
+    pub fn print(&mut self, verbose: &Verbosity, label: &str, n: u64, format: Format) {
+        if !self.ticker.is_enabled() {
+            return;
+        }
+        self.sample(n);
+
+        let first_byte = match self.first_byte {
+            Some(first_byte) => first_byte,
+            None => {
+                println_if_not_quiet!(*verbose, "{label}ramp-up: no byte is transferred");
+                return;
+            }
+        };
+
+        let curve = self.curve();
+        for (elapsed, rate) in &curve {
+            println_if_not_quiet!(
+                *verbose,
+                "{label}ramp-up {:>7.2}s  {}/s",
+                elapsed.as_secs_f64(),
+                HumanReadableUnit::new(*rate as u64, format)
+            );
+        }
+
+        let peak = curve.iter().map(|(_, rate)| *rate).fold(0.0, f64::max);
+        let ramp_up = curve
+            .iter()
+            .find(|(_, rate)| *rate >= peak * 0.9)
+            .map(|(elapsed, _)| format!("{elapsed:#?}"))
+            .unwrap_or_else(|| "unknown".to_string());
+
+        println_if_not_quiet!(
+            *verbose,
+            "{label}time to first byte = {first_byte:#?}, time to 90% of peak {}/s = {ramp_up}",
+            HumanReadableUnit::new(peak as u64, format)
+        );
+
+        if peak <= 0.0 {
+            return;
+        }
+
+        for row in curve.chunks(curve.len().div_ceil(CHART_ROWS)) {
+            let (end, _) = row[row.len() - 1];
+            let avg = row.iter().map(|(_, rate)| rate).sum::<f64>() / row.len() as f64;
+            let width = (avg / peak * CHART_WIDTH as f64).round() as usize;
+
+            println_if_not_quiet!(
+                *verbose,
+                "{label}{:>7.2}s |{:<CHART_WIDTH$}| {}/s",
+                end.as_secs_f64(),
+                "#".repeat(width.min(CHART_WIDTH)),
+                HumanReadableUnit::new(avg as u64, format)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_samples(samples: &[(u64, u64)]) -> Ramp {
+        let mut ramp = Ramp::new(Instant::now(), true);
+        ramp.samples = samples
+            .iter()
+            .map(|&(ms, n)| (Duration::from_millis(ms), n))
+            .collect();
+        ramp
+    }
+
+    #[tokio::test]
+    async fn test_curve() {
+        // Samples every 50ms, averaged over windows of 100ms.
+        let ramp = with_samples(&[
+            (0, 0),
+            (50, 0),
+            (100, 1000),
+            (150, 3000),
+            (200, 6000),
+            (250, 10000),
+            (300, 14000),
+            (350, 15000),
+        ]);
+        assert_eq!(
+            ramp.curve(),
+            [
+                (Duration::from_millis(100), 10_000.0),
+                (Duration::from_millis(200), 50_000.0),
+                (Duration::from_millis(300), 80_000.0),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_curve_late_sample() {
+        // A window ends at the first sample after it, however late.
+        let ramp = with_samples(&[(0, 0), (10, 100), (250, 5000), (260, 5100)]);
+        assert_eq!(ramp.curve(), [(Duration::from_millis(250), 20_000.0)]);
+
+        assert!(with_samples(&[(0, 0), (90, 100)]).curve().is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use super::checksum::Checksum;
+use super::payload::Payload;
+use super::{println_on_level, Level};
+
+use clap_verbosity_flag::Verbosity;
+use openssh::{Error, Session, Stdio};
+use shell_escape::unix::escape;
+use std::fmt;
+use std::str::FromStr;
+
+/// Program used on remote to write the payload to stdout for download.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RemoteSource {
+    /// Pick the first one available on remote from head, dd and cat.
+    Auto,
+    Head,
+    Dd,
+    Cat,
+}
+
+impl FromStr for RemoteSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(RemoteSource::Auto),
+            "head" => Ok(RemoteSource::Head),
+            "dd" => Ok(RemoteSource::Dd),
+            "cat" => Ok(RemoteSource::Cat),
+            s => Err(format!("Expected auto, head, dd or cat, found {s}")),
+        }
+    }
+}
+
+impl fmt::Display for RemoteSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RemoteSource::Auto => "auto",
+            RemoteSource::Head => "head",
+            RemoteSource::Dd => "dd",
+            RemoteSource::Cat => "cat",
+        })
+    }
+}
+
+impl RemoteSource {
+    /// Replace `Auto` with the first source usable on remote.
+    pub async fn resolve(self, session: &Session, verbose: &Verbosity) -> Result<Self, Error> {
+        if self != RemoteSource::Auto {
+            return Ok(self);
+        }
+
+        let probes = [
+            (RemoteSource::Head, "head -c 1 /dev/zero"),
+            (RemoteSource::Dd, "dd if=/dev/zero bs=1 count=1"),
+        ];
+
+        for (source, probe) in probes {
+            println_on_level!(*verbose, Level::Debug, "Probing {source} on remote");
+
+            let status = session
+                .shell(probe)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .await?;
+
+            if status.success() {
+                return Ok(source);
+            }
+        }
+
+        Ok(RemoteSource::Cat)
+    }
+
+    /// Return shell command writing at most `limit` bytes of `payload` to stdout.
+    ///
+    /// Only head stops at `limit` on remote, the others are stopped by
+    /// closing their stdout.
+    pub fn command(self, payload: &Payload, limit: Option<u64>) -> String {
+        // Pattern is generated by yes, the same as the local pattern.
+        let input = match payload {
+            Payload::Zero => Some("/dev/zero".into()),
+            Payload::Random => Some("/dev/urandom".into()),
+            Payload::File(path) => Some(escape(path.to_string_lossy())),
+            Payload::Pattern => None,
+        };
+
+        let command = match (self, limit) {
+            (RemoteSource::Head, Some(limit)) => format!("head -c {limit}"),
+            (RemoteSource::Dd, _) => "dd bs=65536".to_string(),
+            _ => "cat".to_string(),
+        };
+
+        match (self, input) {
+            (RemoteSource::Dd, Some(input)) => format!("{command} if={input}"),
+            (_, Some(input)) => format!("{command} {input}"),
+            (_, None) => format!("yes | {command}"),
+        }
+    }
+
+    /// Same as [`RemoteSource::command`], except that at most `limit` bytes
+    /// are written and their hash is written to stderr.
+    ///
+    /// Stderr of the source is discarded so that only the hash is written
+    /// to stderr, e.g. dd would write its stats before it.
+    pub fn verified_command(self, payload: &Payload, limit: u64, checksum: Checksum) -> String {
+        let mut command = self.command(payload, Some(limit));
+        if self != RemoteSource::Head {
+            command = format!("{command} | head -c {limit}");
+        }
+
+        format!(
+            "exec 3>&1; {{ {command}; }} 2>/dev/null | tee /dev/fd/3 | {} >&2",
+            checksum.program()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        let file = Payload::File("/tmp/a b".into());
+
+        for (source, payload, limit, expected) in [
+            (
+                RemoteSource::Head,
+                &Payload::Zero,
+                Some(100),
+                "head -c 100 /dev/zero",
+            ),
+            (RemoteSource::Head, &Payload::Zero, None, "cat /dev/zero"),
+            (
+                RemoteSource::Head,
+                &Payload::Pattern,
+                Some(100),
+                "yes | head -c 100",
+            ),
+            (
+                RemoteSource::Dd,
+                &Payload::Random,
+                Some(100),
+                "dd bs=65536 if=/dev/urandom",
+            ),
+            (
+                RemoteSource::Dd,
+                &Payload::Pattern,
+                None,
+                "yes | dd bs=65536",
+            ),
+            (RemoteSource::Cat, &file, Some(100), "cat '/tmp/a b'"),
+            (RemoteSource::Dd, &file, None, "dd bs=65536 if='/tmp/a b'"),
+        ] {
+            assert_eq!(source.command(payload, limit), expected);
+        }
+    }
+
+    #[test]
+    fn test_verified_command() {
+        assert_eq!(
+            RemoteSource::Dd.verified_command(&Payload::Zero, 100, Checksum::Md5),
+            "exec 3>&1; { dd bs=65536 if=/dev/zero | head -c 100; } 2>/dev/null \
+             | tee /dev/fd/3 | md5sum >&2"
+        );
+
+        // Run it locally: stdout is the payload and stderr only its hash,
+        // without the stats dd writes to stderr.
+        for source in [RemoteSource::Head, RemoteSource::Dd, RemoteSource::Cat] {
+            let command = source.verified_command(&Payload::Pattern, 5, Checksum::Sha256);
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .output()
+                .unwrap();
+
+            let mut hasher = Checksum::Sha256.hasher();
+            hasher.update(b"y\ny\ny");
+            assert_eq!(output.stdout, b"y\ny\ny", "{command}");
+            assert_eq!(
+                String::from_utf8_lossy(&output.stderr),
+                format!("{}  -\n", hasher.finalize()),
+                "{command}"
+            );
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use super::checksum::{check_received, Checksum};
+use super::download::download_until_stopped;
+use super::payload::Payload;
+use super::transfer::{Direction, Transfer};
+use super::upload::upload_until_stopped;
+use super::{println_on_level, Level, SpeedTestArgs, Tools};
+
+use clap_verbosity_flag::Verbosity;
+use openssh::{Error, Session, Stdio};
+use openssh_sftp_client::file::TokioCompatFile;
+use openssh_sftp_client::{Sftp, SftpOptions};
+use shell_escape::unix::escape;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWriteExt, ReadBuf};
+
+/// Size of the remote file prepared for download, unless --verify
+/// requires exactly --bytes.
+const DOWNLOAD_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+fn sftp_error(err: openssh_sftp_client::Error) -> Error {
+    Error::ChildIo(io::Error::other(err))
+}
+
+/// Return a unique path of temporary file in the remote working directory.
+fn temp_path() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!(".ssh-utils-speedtest-{}-{n}", process::id()).into()
+}
+
+/// Return the hash of the first `limit` bytes of remote file at `path`,
+/// as printed by the remote program.
+async fn remote_checksum(
+    session: &Session,
+    path: &Path,
+    limit: u64,
+    checksum: Checksum,
+) -> Result<Vec<u8>, Error> {
+    let command = format!(
+        "head -c {limit} {} | {}",
+        escape(path.to_string_lossy()),
+        checksum.program()
+    );
+
+    let output = session
+        .shell(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await?;
+
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(Error::Remote(io::Error::other(format!(
+            "Failed to compute {} of {} on remote: {:#?}",
+            checksum.program(),
+            path.display(),
+            output.status
+        ))))
+    }
+}
+
+/// Read the inner reader from the beginning again on EOF.
+struct Rewinding<R> {
+    inner: R,
+    seeking: bool,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncRead for Rewinding<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let mut rewound = false;
+
+        loop {
+            if this.seeking {
+                ready!(Pin::new(&mut this.inner).poll_complete(cx))?;
+                this.seeking = false;
+            }
+
+            let filled = buf.filled().len();
+            ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+            // Return EOF only if the file is empty right after rewinding.
+            if buf.filled().len() != filled || buf.remaining() == 0 || rewound {
+                return Poll::Ready(Ok(()));
+            }
+
+            Pin::new(&mut this.inner).start_seek(SeekFrom::Start(0))?;
+            this.seeking = true;
+            rewound = true;
+        }
+    }
+}
+
+/// Write `len` bytes of `payload` to remote file at `path`.
+async fn prepare(
+    sftp: &Sftp,
+    path: &Path,
+    payload: &Payload,
+    len: u64,
+    buffer_size: usize,
+) -> Result<(), Error> {
+    let mut source = payload
+        .local_source(buffer_size)
+        .await
+        .map_err(Error::ChildIo)?;
+    let file = sftp.create(path).await.map_err(sftp_error)?;
+    let mut file = Box::pin(TokioCompatFile::new(file));
+
+    let mut n = 0;
+    while n < len {
+        let buffer = source.next().await.map_err(Error::ChildIo)?;
+        let remaining: usize = (len - n).try_into().unwrap_or(usize::MAX);
+        let buffer = &buffer[..buffer.len().min(remaining)];
+
+        file.write_all(buffer).await.map_err(Error::ChildIo)?;
+        n += u64::try_from(buffer.len()).unwrap();
+    }
+
+    file.shutdown().await.map_err(Error::ChildIo)
+}
+
+async fn upload(
+    args: &SpeedTestArgs,
+    verbose: &Verbosity,
+    session: &Session,
+    sftp: &Sftp,
+    path: &Path,
+    label: &str,
+    tools: Tools,
+) -> Result<Transfer, Error> {
+    let mut hasher = tools.checksum.map(|checksum| checksum.hasher());
+
+    let file = sftp.create(path).await.map_err(sftp_error)?;
+    let mut file = Box::pin(TokioCompatFile::new(file));
+
+    let (n, measurement) =
+        upload_until_stopped(args, verbose, &mut file, label, &mut hasher).await?;
+
+    // Wait for all bytes to be written to the remote file
+    file.shutdown().await.map_err(Error::ChildIo)?;
+    let transfer = measurement.finish(n);
+
+    if let (Some(hasher), Some(checksum)) = (hasher, tools.checksum) {
+        let output = remote_checksum(session, path, n, checksum).await?;
+        hasher.verify(&output, label, Direction::Upload, verbose)?;
+    }
+
+    Ok(transfer)
+}
+
+async fn download(
+    args: &SpeedTestArgs,
+    verbose: &Verbosity,
+    session: &Session,
+    sftp: &Sftp,
+    path: &Path,
+    label: &str,
+    tools: Tools,
+) -> Result<Transfer, Error> {
+    let mut hasher = tools.checksum.map(|checksum| checksum.hasher());
+    let limit = args.byte_limit();
+
+    let file = sftp.open(path).await.map_err(sftp_error)?;
+    let file = Box::pin(TokioCompatFile::new(file));
+
+    // When verifying, the file is read only once so that its hash can be
+    // compared.
+    let (n, measurement, eof) = if hasher.is_some() {
+        let mut file = file;
+        download_until_stopped(args, verbose, &mut file, limit, label, &mut hasher).await?
+    } else {
+        let mut file = Rewinding {
+            inner: file,
+            seeking: false,
+        };
+        download_until_stopped(args, verbose, &mut file, limit, label, &mut hasher).await?
+    };
+    let transfer = measurement.finish(n);
+
+    match (hasher, tools.checksum) {
+        (Some(hasher), Some(checksum)) if eof || n == limit => {
+            check_received(n, limit, label)?;
+            let output = remote_checksum(session, path, n, checksum).await?;
+            hasher.verify(&output, label, Direction::Download, verbose)?;
+        }
+        (Some(_), _) => println_on_level!(
+            *verbose,
+            Level::Warn,
+            "{label}download is not verified since it is stopped before EOF"
+        ),
+        _ => (),
+    }
+
+    Ok(transfer)
+}
+
+/// Run speed test in `direction` over the sftp subsystem.
+///
+/// Upload writes to a temporary file on remote, while download reads
+/// the remote file of `--payload file:<path>`, or a temporary file
+/// filled with the payload beforehand.
+/// Temporary files are removed afterwards.
+pub async fn speedtest_sftp(
+    args: &SpeedTestArgs,
+    verbose: Verbosity,
+    session: &Session,
+    label: &str,
+    direction: Direction,
+    tools: Tools,
+) -> Result<Transfer, Error> {
+    println_on_level!(verbose, Level::Debug, "Spawning sftp subsystem on remote");
+    let mut child = session
+        .subsystem("sftp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .await?;
+
+    let sftp = Sftp::new(
+        child.stdin().take().unwrap(),
+        child.stdout().take().unwrap(),
+        SftpOptions::default(),
+    )
+    .await
+    .map_err(sftp_error)?;
+
+    let (path, temporary) = match (direction, &args.payload) {
+        (Direction::Download, Payload::File(path)) => (path.clone(), false),
+        _ => (temp_path(), true),
+    };
+
+    let mut res = async {
+        match direction {
+            Direction::Upload => upload(args, &verbose, session, &sftp, &path, label, tools).await,
+            Direction::Download => {
+                if temporary {
+                    let len = if tools.checksum.is_some() {
+                        args.byte_limit()
+                    } else {
+                        args.byte_limit().min(DOWNLOAD_FILE_SIZE)
+                    };
+
+                    println_on_level!(
+                        verbose,
+                        Level::Debug,
+                        "Preparing {len} bytes in {} on remote",
+                        path.display()
+                    );
+                    prepare(&sftp, &path, &args.payload, len, args.buffer_size()).await?;
+                }
+
+                download(args, &verbose, session, &sftp, &path, label, tools).await
+            }
+        }
+    }
+    .await;
+
+    if temporary {
+        println_on_level!(verbose, Level::Debug, "Removing {}", path.display());
+        let remove_res = sftp.fs().remove_file(&path).await.map_err(sftp_error);
+        res = res.and_then(|transfer| remove_res.map(|_| transfer));
+    }
+
+    let close_res = sftp.close().await.map_err(sftp_error);
+    res = res.and_then(|transfer| close_res.map(|_| transfer));
+
+    match child.wait().await {
+        Ok(_) | Err(Error::RemoteProcessTerminated) => (),
+        Err(err) => return Err(err),
+    }
+
+    res
+}
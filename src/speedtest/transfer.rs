@@ -0,0 +1,183 @@
+use super::human_readable_unit::{Format, HumanReadableUnit};
+use super::{println_if_not_quiet, Interval};
+
+use clap_verbosity_flag::Verbosity;
+use std::fmt;
+use std::future::pending;
+use std::time::Duration;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Direction::Upload => "upload",
+            Direction::Download => "download",
+        })
+    }
+}
+
+/// Return bytes per second.
+pub fn rate(n: u64, elapsed: Duration, format: Format) -> HumanReadableUnit {
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { n as f64 / secs } else { 0.0 };
+
+    HumanReadableUnit::new(rate as u64, format)
+}
+
+/// Result of one transfer.
+#[derive(Debug, Copy, Clone)]
+pub struct Transfer {
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Transfer {
+    /// Aggregate transfers running in parallel.
+    pub fn sum(transfers: &[Transfer]) -> Self {
+        Self {
+            bytes: transfers.iter().map(|transfer| transfer.bytes).sum(),
+            elapsed: transfers
+                .iter()
+                .map(|transfer| transfer.elapsed)
+                .max()
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn rate(&self, format: Format) -> HumanReadableUnit {
+        rate(self.bytes, self.elapsed, format)
+    }
+
+    pub fn print(&self, label: &str, direction: Direction, format: Format) {
+        println!(
+            "{label}{} is {direction}ed in {:#?}, {direction} speed = {}/s",
+            HumanReadableUnit::new(self.bytes, format),
+            self.elapsed,
+            self.rate(format)
+        );
+    }
+}
+
+/// Interval which can be disabled.
+#[derive(Debug)]
+pub struct Ticker(Option<tokio::time::Interval>);
+
+impl Ticker {
+    /// Tick every `period` from `start + period`, or never if `period` is
+    /// `None`.
+    pub fn new(start: Instant, period: Option<Duration>) -> Self {
+        Self(period.map(|period| {
+            let mut interval = interval_at(start + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval
+        }))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Cancel safe, never completes if disabled.
+    pub async fn tick(&mut self) {
+        match self.0.as_mut() {
+            Some(interval) => {
+                interval.tick().await;
+            }
+            None => pending().await,
+        }
+    }
+}
+
+/// Print the number of bytes transferred in every report interval.
+#[derive(Debug)]
+pub struct Reporter<'a> {
+    label: &'a str,
+    format: Format,
+    ticker: Ticker,
+    start: Instant,
+    last: Instant,
+    last_n: u64,
+}
+
+impl<'a> Reporter<'a> {
+    pub fn new(label: &'a str, start: Instant, report_interval: Interval, format: Format) -> Self {
+        let period = (!report_interval.0.is_zero()).then_some(report_interval.0);
+
+        Self {
+            label,
+            format,
+            ticker: Ticker::new(start, period),
+            start,
+            last: start,
+            last_n: 0,
+        }
+    }
+
+    /// Cancel safe, never completes if reporting is disabled.
+    pub async fn tick(&mut self) {
+        self.ticker.tick().await
+    }
+
+    pub fn report(&mut self, verbose: &Verbosity, n: u64, measurement: &Measurement) {
+        let now = Instant::now();
+        let bytes = n - self.last_n;
+
+        println_if_not_quiet!(
+            *verbose,
+            "{}{:>7.2}-{:<7.2}s  {}  {}/s{}",
+            self.label,
+            (self.last - self.start).as_secs_f64(),
+            (now - self.start).as_secs_f64(),
+            HumanReadableUnit::new(bytes, self.format),
+            rate(bytes, now - self.last, self.format),
+            if measurement.omitted.is_none() {
+                "  (omitted)"
+            } else {
+                ""
+            }
+        );
+
+        self.last = now;
+        self.last_n = n;
+    }
+}
+
+/// Number of bytes transferred since the end of the warm-up.
+#[derive(Debug)]
+pub struct Measurement {
+    start: Instant,
+    pub omit_until: Instant,
+    pub omitted: Option<u64>,
+}
+
+impl Measurement {
+    pub fn new(start: Instant, omit: Interval) -> Self {
+        Self {
+            start,
+            omit_until: start + omit.0,
+            omitted: if omit.0.is_zero() { Some(0) } else { None },
+        }
+    }
+
+    /// Record the number of bytes transferred during the warm-up.
+    pub fn end_warm_up(&mut self, n: u64) {
+        self.start = self.omit_until;
+        self.omitted = Some(n);
+    }
+
+    /// Return bytes transferred and time elapsed since the warm-up ended.
+    ///
+    /// If the transfer ended during the warm-up, the whole transfer is measured.
+    pub fn finish(&self, n: u64) -> Transfer {
+        Transfer {
+            bytes: n - self.omitted.unwrap_or(0),
+            elapsed: self.start.elapsed(),
+        }
+    }
+}
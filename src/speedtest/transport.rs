@@ -0,0 +1,36 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Channel the data of speed test is transferred over.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Transport {
+    /// stdin/stdout of a process executed on remote.
+    Exec,
+    /// Temporary file on remote read and written via the sftp subsystem.
+    Sftp,
+    /// Local port forward to a process on remote relaying the data.
+    Forward,
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exec" => Ok(Transport::Exec),
+            "sftp" => Ok(Transport::Sftp),
+            "forward" => Ok(Transport::Forward),
+            s => Err(format!("Expected exec, sftp or forward, found {s}")),
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Transport::Exec => "exec",
+            Transport::Sftp => "sftp",
+            Transport::Forward => "forward",
+        })
+    }
+}
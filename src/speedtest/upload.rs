@@ -1,90 +1,179 @@
-use super::human_readable_unit::HumanReadableUnit;
-use super::{println_on_level, Level};
+use super::checksum::Hasher;
+use super::pacing::Pacer;
+use super::payload::PayloadSource;
+use super::ramp::Ramp;
+use super::transfer::{Direction, Measurement, Reporter, Transfer};
+use super::{println_on_level, Level, SpeedTestArgs, Tools};
 
 use clap_verbosity_flag::Verbosity;
-use openssh::{ChildStdin, Error, Session, Stdio};
+use openssh::{Error, Session, Stdio};
 use std::io;
-use std::time::{Duration, Instant};
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::signal::ctrl_c;
-use tokio::time::{interval, MissedTickBehavior};
+use tokio::time::{sleep_until, Instant};
 
-async fn upload(
-    child_stdin: &mut ChildStdin,
+async fn upload<W: AsyncWrite + Unpin>(
+    writer: &mut W,
     n: &mut u64,
-    buffer: &[u8],
+    limit: u64,
+    source: &mut PayloadSource,
+    hasher: &mut Option<Hasher>,
+    pacer: &mut Option<Pacer>,
     verbose: Verbosity,
 ) -> Result<(), Error> {
-    loop {
+    while *n < limit {
+        let buffer = source.next().await.map_err(Error::ChildIo)?;
+
+        let remaining: usize = (limit - *n).try_into().unwrap_or(usize::MAX);
+        let mut len = buffer.len().min(remaining);
+        if let Some(pacer) = pacer {
+            len = pacer.acquire(len).await;
+        }
+        let buffer = &buffer[..len];
+
         println_on_level!(verbose, Level::Debug, "Uploading");
-        let cnt = child_stdin.write(buffer).await.map_err(Error::ChildIo)?;
+        if let Some(pacer) = pacer {
+            pacer.start_write();
+        }
+        let cnt = writer.write(buffer).await.map_err(Error::ChildIo)?;
+        if let Some(pacer) = pacer {
+            pacer.written(cnt);
+        }
+        if let Some(hasher) = hasher {
+            hasher.update(&buffer[..cnt]);
+        }
+
         let cnt: u64 = cnt.try_into().unwrap();
         if cnt == 0 {
             return Err(Error::ChildIo(io::Error::new(
                 io::ErrorKind::WriteZero,
-                "Write to child_stdin failed in upload",
+                "Write failed in upload",
             )));
         }
 
         *n += cnt;
     }
+
+    Ok(())
 }
 
-pub async fn speedtest_upload(verbose: Verbosity, session: &Session) -> Result<(), Error> {
-    let mut interval = interval(Duration::from_secs(5));
-    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+pub async fn speedtest_upload(
+    args: &SpeedTestArgs,
+    verbose: Verbosity,
+    session: &Session,
+    label: &str,
+    tools: Tools,
+) -> Result<Transfer, Error> {
+    // Verify the upload by hashing stdin on remote instead of discarding it.
+    let (program, program_args): (_, &[&str]) = match tools.checksum {
+        Some(checksum) => (checksum.program(), &[]),
+        None => ("dd", &["of=/dev/null"]),
+    };
+    let mut hasher = tools.checksum.map(|checksum| checksum.hasher());
 
-    println_on_level!(verbose, Level::Debug, "Spawning process dd on remote");
+    println_on_level!(
+        verbose,
+        Level::Debug,
+        "Spawning process {program} on remote"
+    );
     let mut child = session
-        .command("dd")
-        .arg("of=/dev/null")
+        .command(program)
+        .args(program_args)
         .stdin(Stdio::piped())
-        .stdout(Stdio::null())
+        .stdout(if hasher.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
         .stderr(Stdio::null())
         .spawn()
         .await?;
 
     let mut child_stdin = child.stdin().take().unwrap();
+    let (n, measurement) =
+        upload_until_stopped(args, &verbose, &mut child_stdin, label, &mut hasher).await?;
+    drop(child_stdin);
+
+    let mut output = Vec::new();
+    if let Some(mut child_stdout) = child.stdout().take() {
+        child_stdout
+            .read_to_end(&mut output)
+            .await
+            .map_err(Error::ChildIo)?;
+    }
+
+    // Wait for all bytes to be read by remote process
+    let exit_status = child.wait().await?;
+    let transfer = measurement.finish(n);
+
+    if !exit_status.success() {
+        println_on_level!(
+            verbose,
+            Level::Error,
+            "Failed to execute {program} on remote: {exit_status:#?}"
+        );
+    }
+
+    if let Some(hasher) = hasher {
+        hasher.verify(&output, label, Direction::Upload, &verbose)?;
+    }
+
+    Ok(transfer)
+}
+
+/// Upload to `writer` until the byte limit or the deadline is reached,
+/// or ctrl_c is received.
+///
+/// Return the number of bytes uploaded.
+pub async fn upload_until_stopped<W: AsyncWrite + Unpin>(
+    args: &SpeedTestArgs,
+    verbose: &Verbosity,
+    writer: &mut W,
+    label: &str,
+    hasher: &mut Option<Hasher>,
+) -> Result<(u64, Measurement), Error> {
     let mut n = 0;
 
-    // Buffer is 4096 bytes large, the size of pipe.
-    let buffer: Vec<u8> = (0..255_u8).cycle().take(4096).collect();
+    let mut source = args
+        .payload
+        .local_source(args.buffer_size())
+        .await
+        .map_err(Error::ChildIo)?;
 
     let shutdown_requested = ctrl_c();
     tokio::pin!(shutdown_requested);
 
     let instant = Instant::now();
+    let deadline = args.deadline(instant);
+    let mut measurement = Measurement::new(instant, args.omit);
+    let mut reporter = Reporter::new(label, instant, args.report_interval, args.format());
+    let mut ramp = Ramp::new(instant, args.ramp);
+    let mut pacer = args.bitrate.map(Pacer::new);
 
-    while n < 4096 * 100 {
+    loop {
         tokio::select! {
-            res = upload(&mut child_stdin, &mut n, &buffer, verbose.clone()) => res?,
-            _ = interval.tick() => (),
+            res = upload(writer, &mut n, args.byte_limit(), &mut source, hasher, &mut pacer, verbose.clone()) => {
+                res?;
+                // break on reaching the byte limit
+                break
+            },
+            _ = reporter.tick() => reporter.report(verbose, n, &measurement),
+            _ = ramp.tick() => ramp.sample(n),
+            _ = sleep_until(measurement.omit_until), if measurement.omitted.is_none() => {
+                measurement.end_warm_up(n)
+            },
+            _ = sleep_until(deadline.unwrap_or(instant)), if deadline.is_some() => break,
             _ = &mut shutdown_requested => {
-                println_on_level!(verbose, Level::Debug, "Ctrl C signal received");
+                println_on_level!(*verbose, Level::Debug, "Ctrl C signal received");
                 break
             }
         }
     }
 
-    drop(child_stdin);
-
-    // Wait for all bytes to be read by dd
-    let exit_status = child.wait().await?;
-    let elapsed = instant.elapsed();
-
-    if !exit_status.success() {
-        println_on_level!(
-            verbose,
-            Level::Error,
-            "Failed to execute cat on remote: {exit_status:#?}"
-        );
+    ramp.print(verbose, label, n, args.format());
+    if let Some(pacer) = pacer {
+        pacer.print(verbose, label, n, instant.elapsed(), args.format());
     }
 
-    println!(
-        "{} is uploaded in {elapsed:#?}, upload speed = {}/s",
-        HumanReadableUnit::new(n),
-        HumanReadableUnit::new(n / elapsed.as_secs())
-    );
-
-    Ok(())
+    Ok((n, measurement))
 }
@@ -0,0 +1,502 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Maximum depth of nested Include, same as ssh.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Return true if `name` matches `pattern` with wildcards `*` and `?`.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+
+    // Position in pattern and name after the last `*`, to backtrack to.
+    let mut star = None;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Return true if `name` matches the comma-separated `patterns`, where
+/// patterns prefixed with `!` exclude the names they match.
+pub fn matches_list(patterns: &str, name: &str) -> bool {
+    let mut matched = false;
+
+    for pattern in patterns.split(',') {
+        match pattern.strip_prefix('!') {
+            Some(pattern) if matches(pattern, name) => return false,
+            Some(_) => (),
+            None => matched |= matches(pattern, name),
+        }
+    }
+
+    matched
+}
+
+fn is_pattern(s: &str) -> bool {
+    s.contains(['*', '?', '!'])
+}
+
+/// Split a line into keyword and arguments, handling `keyword=value`
+/// and double-quoted arguments.
+fn tokenize(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let (keyword, rest) = match line.find(|c: char| c.is_whitespace() || c == '=') {
+        Some(i) => (&line[..i], &line[i..]),
+        None => (line, ""),
+    };
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+
+    let mut tokens = vec![keyword.to_string()];
+    let mut token = String::new();
+    let mut quoted = false;
+    let mut in_token = false;
+
+    for c in rest.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_token = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if in_token {
+                    tokens.push(std::mem::take(&mut token));
+                    in_token = false;
+                }
+            }
+            c => {
+                token.push(c);
+                in_token = true;
+            }
+        }
+    }
+    if in_token {
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+/// Expand `~` and wildcards in the path of an Include, relative paths
+/// are relative to `dir`.
+fn expand_include(pattern: &str, dir: &Path) -> Vec<PathBuf> {
+    let path = match pattern.strip_prefix("~/") {
+        Some(rest) => match dirs::home_dir() {
+            Some(home) => home.join(rest),
+            None => return Vec::new(),
+        },
+        None => dir.join(pattern),
+    };
+
+    let mut paths = vec![PathBuf::new()];
+    for component in path.components() {
+        let component = match component {
+            Component::Normal(component) => component.to_string_lossy(),
+            component => {
+                for path in &mut paths {
+                    path.push(component);
+                }
+                continue;
+            }
+        };
+
+        if !is_pattern(&component) {
+            for path in &mut paths {
+                path.push(&*component);
+            }
+            continue;
+        }
+
+        paths = paths
+            .iter()
+            .flat_map(|dir| {
+                let mut names: Vec<_> = fs::read_dir(dir)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .filter(|name| !name.starts_with('.') && matches(&component, name))
+                    .collect();
+                names.sort();
+                names.into_iter().map(move |name| dir.join(name))
+            })
+            .collect();
+    }
+
+    paths
+}
+
+/// A concrete host declared in `Host` or `Match host`.
+///
+/// `hostname`, `user` and `port` are the values declared in the blocks
+/// naming the host, not the effective ones, which may also come from
+/// wildcard blocks like `Host *`.
+#[derive(Debug, Clone)]
+pub struct HostEntry {
+    pub name: String,
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<String>,
+    /// File stem of the included file declaring the host, selectable
+    /// as `@group`. This is an ssh-utils convention, ssh_config itself
+    /// has no groups.
+    pub group: Option<String>,
+}
+
+/// Concrete hosts declared in ssh_config.
+#[derive(Debug, Default)]
+pub struct SshConfig {
+    pub hosts: Vec<HostEntry>,
+}
+
+impl SshConfig {
+    /// Load `config_file`, or ~/.ssh/config and /etc/ssh/ssh_config,
+    /// following Include.
+    pub fn load(config_file: Option<&Path>) -> io::Result<Self> {
+        let mut config = Self::default();
+
+        match config_file {
+            Some(config_file) => config.parse_file(config_file, user_dir(), None, &[], 0)?,
+            None => {
+                if let Some(dir) = user_dir() {
+                    let path = dir.join("config");
+                    if path.exists() {
+                        config.parse_file(&path, Some(dir), None, &[], 0)?;
+                    }
+                }
+
+                let path = Path::new("/etc/ssh/ssh_config");
+                if path.exists() {
+                    config.parse_file(path, Some(PathBuf::from("/etc/ssh")), None, &[], 0)?;
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Parse `path`, whose lines before the first Host/Match apply to the
+    /// hosts in `block`, the indices of hosts of the including block.
+    fn parse_file(
+        &mut self,
+        path: &Path,
+        dir: Option<PathBuf>,
+        group: Option<&str>,
+        block: &[usize],
+        depth: usize,
+    ) -> io::Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Too many nested Include at {}", path.display()),
+            ));
+        }
+
+        let content = fs::read_to_string(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+
+        // Indices of the hosts of the current Host/Match block, hosts
+        // declared by an Include within it are not part of the block.
+        let mut block = block.to_vec();
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let tokens = tokenize(line);
+            let keyword = tokens[0].to_ascii_lowercase();
+            let args = &tokens[1..];
+
+            match &*keyword {
+                "host" => {
+                    block = self.declare(args.iter().map(String::as_str), group);
+                }
+                "match" => {
+                    block.clear();
+
+                    let mut args = args.iter();
+                    while let Some(criteria) = args.next() {
+                        let criteria = criteria.to_ascii_lowercase();
+                        if criteria == "host" || criteria == "originalhost" {
+                            if let Some(hosts) = args.next() {
+                                block.extend(self.declare(hosts.split(','), group));
+                            }
+                        }
+                    }
+                }
+                "include" => {
+                    let dir = dir.clone().unwrap_or_default();
+                    for pattern in args {
+                        // Like ssh, an Include matching no file is ignored.
+                        for path in expand_include(pattern, &dir)
+                            .into_iter()
+                            .filter(|path| path.exists())
+                        {
+                            let stem = path
+                                .file_stem()
+                                .map(|stem| stem.to_string_lossy().into_owned());
+                            self.parse_file(
+                                &path,
+                                Some(dir.clone()),
+                                stem.as_deref(),
+                                &block,
+                                depth + 1,
+                            )?;
+                        }
+                    }
+                }
+                "hostname" | "user" | "port" => {
+                    for &i in &block {
+                        let host = &mut self.hosts[i];
+                        let value = match &*keyword {
+                            "hostname" => &mut host.hostname,
+                            "user" => &mut host.user,
+                            _ => &mut host.port,
+                        };
+                        // The first value obtained is used, like ssh.
+                        if value.is_none() {
+                            *value = args.first().cloned();
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Declare the concrete hosts in `names`, and return the indices of
+    /// them including those declared before.
+    fn declare<'a>(
+        &mut self,
+        names: impl Iterator<Item = &'a str>,
+        group: Option<&str>,
+    ) -> Vec<usize> {
+        let mut indices = Vec::new();
+
+        for name in names {
+            if is_pattern(name) {
+                continue;
+            }
+
+            match self.hosts.iter().position(|host| host.name == name) {
+                Some(i) => indices.push(i),
+                None => {
+                    indices.push(self.hosts.len());
+                    self.hosts.push(HostEntry {
+                        name: name.to_string(),
+                        hostname: None,
+                        user: None,
+                        port: None,
+                        group: group.map(str::to_string),
+                    });
+                }
+            }
+        }
+
+        indices
+    }
+
+    /// Return hosts selected by `@group`, the hosts declared in included
+    /// files named group, or comma-separated patterns like `web-*,!web-3`.
+    pub fn select(&self, selector: &str) -> Vec<&HostEntry> {
+        self.hosts
+            .iter()
+            .filter(|host| match selector.strip_prefix('@') {
+                Some(group) => host.group.as_deref() == Some(group),
+                None => matches_list(selector, &host.name),
+            })
+            .collect()
+    }
+}
+
+fn user_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tempfile::TempDir;
+
+    fn load(files: &[(&str, &str)]) -> SshConfig {
+        let dir = TempDir::new().unwrap();
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        let mut config = SshConfig::default();
+        let path = dir.path().join(files[0].0);
+        config
+            .parse_file(&path, Some(dir.path().to_path_buf()), None, &[], 0)
+            .unwrap();
+        config
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("web-*", "web-"));
+        assert!(matches("w?b-1", "web-1"));
+        assert!(!matches("web-?", "web-10"));
+
+        // Backtracking to the last '*'.
+        assert!(matches("*b*1", "web-1-b-1"));
+        assert!(!matches("*-2", "web-1"));
+        assert!(matches("**", ""));
+    }
+
+    #[test]
+    fn test_matches_list() {
+        assert!(matches_list("web-*,!web-3", "web-1"));
+        assert!(!matches_list("web-*,!web-3", "web-3"));
+        assert!(!matches_list("!web-3,web-*", "web-3"));
+        assert!(matches_list("db-1,web-*", "db-1"));
+        assert!(!matches_list("!web-3", "web-1"));
+    }
+
+    #[test]
+    fn test_tokenize() {
+        for (line, expected) in [
+            ("Host web-1 web-2", vec!["Host", "web-1", "web-2"]),
+            ("  User   alice  ", vec!["User", "alice"]),
+            ("Port=2222", vec!["Port", "2222"]),
+            ("Port = 2222", vec!["Port", "2222"]),
+            (
+                r#"Include "my dir/*.conf" b"#,
+                vec!["Include", "my dir/*.conf", "b"],
+            ),
+            (r#"User """#, vec!["User", ""]),
+            ("Host", vec!["Host"]),
+        ] {
+            assert_eq!(tokenize(line), expected, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = load(&[(
+            "config",
+            "# comment\n\
+             Host web-1 web-2 web-*\n\
+             \tHostName=10.0.0.1\n\
+             \tUser alice\n\
+             Host web-2\n\
+             \tHostName 10.0.0.2\n\
+             \tPort 2222\n\
+             Match user bob host db-1,db-?\n\
+             \tUser bob\n\
+             Host *\n\
+             \tUser nobody\n",
+        )]);
+
+        let [web_1, web_2, db_1] = &config.hosts[..] else {
+            panic!("Unexpected {:?}", config.hosts);
+        };
+        assert_eq!(web_1.name, "web-1");
+        assert_eq!(web_1.hostname.as_deref(), Some("10.0.0.1"));
+        assert_eq!(web_1.user.as_deref(), Some("alice"));
+        assert_eq!(web_1.port, None);
+
+        // The first value obtained is used.
+        assert_eq!(web_2.hostname.as_deref(), Some("10.0.0.1"));
+        assert_eq!(web_2.port.as_deref(), Some("2222"));
+
+        assert_eq!(db_1.user.as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn test_parse_include() {
+        let config = load(&[
+            (
+                "config",
+                "Host web-1\n\
+                 \tInclude config.d/* missing /nonexistent/extra_config\n\
+                 \tUser alice\n\
+                 Host db-1\n\
+                 \tPort 2222\n",
+            ),
+            (
+                "config.d/prod",
+                "User root\nHost prod-1\n\tHostName 10.0.0.1\n",
+            ),
+            ("config.d/.hidden", "Host hidden\n"),
+        ]);
+
+        let [web_1, prod_1, db_1] = &config.hosts[..] else {
+            panic!("Unexpected {:?}", config.hosts);
+        };
+
+        // Lines before the first Host of an included file belong to the
+        // including block, and lines after an Include do not apply to the
+        // hosts it declares.
+        assert_eq!(web_1.user.as_deref(), Some("root"));
+        assert_eq!(web_1.group, None);
+
+        assert_eq!(prod_1.hostname.as_deref(), Some("10.0.0.1"));
+        assert_eq!(prod_1.user, None);
+        assert_eq!(prod_1.group.as_deref(), Some("prod"));
+
+        assert_eq!(db_1.port.as_deref(), Some("2222"));
+    }
+
+    #[test]
+    fn test_parse_include_loop() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config");
+        fs::write(&path, "Include config\n").unwrap();
+
+        let err = SshConfig::default()
+            .parse_file(&path, Some(dir.path().to_path_buf()), None, &[], 0)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_select() {
+        let config = load(&[
+            ("config", "Host web-1 web-3 web-10\nInclude db\n"),
+            ("db", "Host db-1 db-2\n"),
+        ]);
+
+        let select = |selector| -> Vec<_> {
+            config
+                .select(selector)
+                .into_iter()
+                .map(|host| host.name.as_str())
+                .collect()
+        };
+
+        assert_eq!(select("web-1"), ["web-1"]);
+        assert_eq!(select("web-*,!web-3"), ["web-1", "web-10"]);
+        assert_eq!(select("@db"), ["db-1", "db-2"]);
+        assert!(select("@web").is_empty());
+    }
+}
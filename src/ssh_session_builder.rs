@@ -1,18 +1,239 @@
-use openssh::{Error, Session, SessionBuilder};
+use super::Destination;
 
-#[derive(Debug)]
-pub struct SshSessionBuilder<'dest>(SessionBuilder, &'dest str);
+use openssh::{Error, KnownHosts, Session, SessionBuilder};
+use std::convert::Infallible;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tempfile::NamedTempFile;
+use tokio::process;
 
-impl<'dest> SshSessionBuilder<'dest> {
-    pub fn new(builder: SessionBuilder, dest: &'dest str) -> Self {
-        Self(builder, dest)
+/// Where to find the control socket of an already running ssh multiplex master.
+#[derive(Debug, Clone)]
+pub enum ControlPath {
+    /// Discover it from `ControlPath` in ssh_config via `ssh -G`.
+    Auto,
+    Path(PathBuf),
+}
+
+impl FromStr for ControlPath {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "auto" => ControlPath::Auto,
+            path => ControlPath::Path(path.into()),
+        })
+    }
+}
+
+/// How to check the host key against known_hosts, parsed from
+/// "strict", "add" or "accept".
+#[derive(Debug, Clone)]
+pub struct KnownHostsPolicy(pub KnownHosts);
+
+impl FromStr for KnownHostsPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self(KnownHosts::Strict)),
+            "add" => Ok(Self(KnownHosts::Add)),
+            "accept" => Ok(Self(KnownHosts::Accept)),
+            s => Err(format!("Expected strict, add or accept, found {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SshSessionBuilder<'a> {
+    builder: SessionBuilder,
+    dest: &'a Destination,
+    config_file: Option<&'a Path>,
+    control_path: Option<&'a ControlPath>,
+    user: Option<&'a str>,
+    port: Option<u16>,
+    jump_hosts: &'a [Destination],
+}
+
+impl<'a> SshSessionBuilder<'a> {
+    pub fn new(builder: SessionBuilder, dest: &'a Destination) -> Self {
+        let mut this = Self {
+            builder,
+            dest,
+            config_file: None,
+            control_path: None,
+            user: None,
+            port: None,
+            jump_hosts: &[],
+        };
+        this.user(dest.user.as_deref()).port(dest.port);
+        this
+    }
+
+    /// Login user, also used to discover `ControlPath`.
+    ///
+    /// The user in the destination takes precedence.
+    pub fn user(&mut self, user: Option<&'a str>) -> &mut Self {
+        let user = self.dest.user.as_deref().or(user);
+        if let Some(user) = user {
+            self.builder.user(user.to_string());
+        }
+        self.user = user;
+        self
+    }
+
+    /// Port of remote, also used to discover `ControlPath`.
+    ///
+    /// The port in the destination takes precedence.
+    pub fn port(&mut self, port: Option<u16>) -> &mut Self {
+        let port = self.dest.port.or(port);
+        if let Some(port) = port {
+            self.builder.port(port);
+        }
+        self.port = port;
+        self
+    }
+
+    /// Config file passed to ssh, used to discover `ControlPath`.
+    pub fn config_file(&mut self, config_file: Option<&'a Path>) -> &mut Self {
+        self.config_file = config_file;
+        self
+    }
+
+    /// Resume an existing ssh multiplex master instead of creating a new one.
+    pub fn control_path(&mut self, control_path: Option<&'a ControlPath>) -> &mut Self {
+        self.control_path = control_path;
+        self
+    }
+
+    /// Connect through the jump hosts in order, also used to discover
+    /// `ControlPath`.
+    pub fn jump_hosts(&mut self, jump_hosts: &'a [Destination]) -> &mut Self {
+        if !jump_hosts.is_empty() {
+            self.builder
+                .jump_hosts(jump_hosts.iter().map(ToString::to_string));
+        }
+        self.jump_hosts = jump_hosts;
+        self
+    }
+
+    /// Only takes effect on new ssh multiplex master.
+    pub fn compression(&mut self, compression: bool) -> &mut Self {
+        self.builder.compression(compression);
+        self
+    }
+
+    /// Set ssh options taking precedence over the ssh_config in use.
+    ///
+    /// The options are written to a temporary config file that includes the
+    /// ssh_config otherwise used, which must be kept alive until connected.
+    ///
+    /// Only takes effect on new ssh multiplex master.
+    pub fn options(&mut self, options: &[(&str, &str)]) -> io::Result<NamedTempFile> {
+        let mut file = tempfile::Builder::new()
+            .prefix("ssh-utils-config")
+            .tempfile()?;
+
+        for (key, value) in options {
+            writeln!(file, "{key} {value}")?;
+        }
+
+        let included = match self.config_file {
+            Some(config_file) => vec![config_file.canonicalize()?],
+            None => dirs::home_dir()
+                .map(|home| home.join(".ssh").join("config"))
+                .into_iter()
+                .chain([PathBuf::from("/etc/ssh/ssh_config")])
+                .filter(|path| path.exists())
+                .collect(),
+        };
+        for path in included {
+            writeln!(file, "Include \"{}\"", path.display())?;
+        }
+        file.flush()?;
+
+        self.builder.config_file(file.path());
+
+        Ok(file)
+    }
+
+    /// Return true if an existing ssh multiplex master is resumed.
+    pub fn is_resumed(&self) -> bool {
+        self.control_path.is_some()
     }
 
     pub async fn connect(&self) -> Result<Session, Error> {
-        self.0.connect_mux(self.1).await
+        let ctl = match self.control_path {
+            None => return self.builder.connect_mux(&self.dest.host).await,
+            Some(ControlPath::Path(path)) => path.clone(),
+            Some(ControlPath::Auto) => self.discover_control_path().await?,
+        };
+
+        let session = Session::resume_mux(ctl.into_boxed_path(), None);
+        match session.check().await {
+            Ok(()) => Ok(session),
+            Err(err) => {
+                // Do not terminate a master we do not own.
+                session.detach();
+                Err(err)
+            }
+        }
+    }
+
+    /// Close the session, except for a resumed master, which is left running
+    /// so that other users of it are not disconnected.
+    pub async fn close(&self, session: Session) -> Result<(), Error> {
+        if self.is_resumed() {
+            session.detach();
+            Ok(())
+        } else {
+            session.close().await
+        }
+    }
+
+    pub fn dest(&self) -> &'a Destination {
+        self.dest
     }
 
-    pub fn dest(&self) -> &'dest str {
-        self.1
+    async fn discover_control_path(&self) -> Result<PathBuf, Error> {
+        let mut cmd = process::Command::new("ssh");
+        if let Some(config_file) = self.config_file {
+            cmd.arg("-F").arg(config_file);
+        }
+        if let Some(user) = self.user {
+            cmd.arg("-l").arg(user);
+        }
+        if let Some(port) = self.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        if !self.jump_hosts.is_empty() {
+            let jump_hosts: Vec<_> = self.jump_hosts.iter().map(ToString::to_string).collect();
+            cmd.arg("-J").arg(jump_hosts.join(","));
+        }
+        let output = cmd
+            .arg("-G")
+            .arg(&self.dest.host)
+            .output()
+            .await
+            .map_err(Error::Connect)?;
+
+        if !output.status.success() {
+            return Err(Error::Connect(io::Error::other(
+                String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            )));
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .find_map(|line| line.strip_prefix("controlpath "))
+            .filter(|path| *path != "none")
+            .map(PathBuf::from)
+            .ok_or_else(|| {
+                Error::Connect(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No ControlPath is configured for {}", self.dest),
+                ))
+            })
     }
 }